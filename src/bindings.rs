@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::error::Error;
+
+// Everything a key can be bound to.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Attack,
+    PreviousWeapon,
+    NextWeapon,
+    Pause,
+    Quit,
+    QuickSave,
+    QuickLoad,
+}
+
+// Which keys trigger each action for one player. Keys are named the way
+// SDL names them (e.g. "Up", "Space", "W", "Left Shift"), ignoring case.
+// See keys/default.ron for the format.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Bindings {
+    keys: BTreeMap<Action, Vec<String>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        Bindings::parse_overrides(include_str!("../keys/default.ron"))
+            .expect("default key bindings are valid")
+    }
+}
+
+impl Bindings {
+    // The keys each player starts with: keys/default.ron for the first,
+    // keys/player2.ron for the second, and none for the rest, who need a
+    // controller or their own bindings.
+    pub fn for_player(player: usize) -> Bindings {
+        match player {
+            0 => Bindings::default(),
+            1 => Bindings::parse_overrides(include_str!("../keys/player2.ron"))
+                .expect("second player's key bindings are valid"),
+            _ => Bindings {
+                keys: BTreeMap::new(),
+            },
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, player: usize) -> Result<Bindings, Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        Bindings::parse(&text, player).map_err(|e| match e {
+            Error::DeserializeError(e) => Error::ConfigError(format!("{}: {}", path.display(), e)),
+            e => e,
+        })
+    }
+
+    // Actions left out of the text keep the player's default keys. Bind
+    // one to an empty list to unbind it.
+    pub fn parse(text: &str, player: usize) -> Result<Bindings, Error> {
+        let mut bindings = Bindings::for_player(player);
+        bindings.keys.extend(Bindings::parse_overrides(text)?.keys);
+        bindings.validate()?;
+        Ok(bindings)
+    }
+
+    fn parse_overrides(text: &str) -> Result<Bindings, Error> {
+        Ok(ron::from_str(text)?)
+    }
+
+    // A key can only do one thing.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut seen = HashMap::new();
+        for (&action, keys) in &self.keys {
+            for key in keys {
+                if let Some(other) = seen.insert(key.to_ascii_lowercase(), action) {
+                    return Err(Error::ConfigError(format!(
+                        "key {} is bound to both {:?} and {:?}",
+                        key, other, action
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Players sharing a keyboard can't share keys either.
+    pub fn validate_players(players: &[Bindings]) -> Result<(), Error> {
+        let mut seen = HashMap::new();
+        for (player, bindings) in players.iter().enumerate() {
+            for (key, _) in bindings.iter() {
+                match seen.insert(key.to_ascii_lowercase(), player) {
+                    Some(other) if other != player => {
+                        return Err(Error::ConfigError(format!(
+                            "key {} is bound for both player {} and player {}",
+                            key,
+                            other + 1,
+                            player + 1
+                        )));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn keys(&self, action: Action) -> &[String] {
+        self.keys.get(&action).map_or(&[], |keys| keys)
+    }
+
+    // Every bound key with its action, sorted by action.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Action)> {
+        self.keys
+            .iter()
+            .flat_map(|(&action, keys)| keys.iter().map(move |key| (key.as_str(), action)))
+    }
+
+    pub fn action(&self, key: &str) -> Option<Action> {
+        self.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, action)| action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bindings_default() {
+        let bindings = Bindings::default();
+        assert!(bindings.validate().is_ok());
+        assert_eq!(bindings.action("Up"), Some(Action::MoveUp));
+        assert_eq!(bindings.action("space"), Some(Action::Attack));
+        assert_eq!(bindings.action("Escape"), Some(Action::Quit));
+        assert_eq!(bindings.action("F5"), Some(Action::QuickSave));
+        assert_eq!(bindings.action("W"), None);
+        assert_eq!(bindings.keys(Action::MoveLeft), ["Left"]);
+    }
+
+    #[test]
+    fn bindings_parse() {
+        // Several keys per action, and the rest left as they were.
+        let bindings = Bindings::parse("{MoveUp: [\"Up\", \"W\"], Attack: []}", 0).unwrap();
+        assert_eq!(bindings.action("w"), Some(Action::MoveUp));
+        assert_eq!(bindings.action("Up"), Some(Action::MoveUp));
+        assert_eq!(bindings.action("Space"), None);
+        assert_eq!(bindings.action("Down"), Some(Action::MoveDown));
+
+        assert!(Bindings::parse(include_str!("../keys/wasd.ron"), 0).is_ok());
+        assert!(Bindings::parse("{MoveUp: [\"Down\"]}", 0).is_err());
+        assert!(Bindings::parse("{Jump: [\"J\"]}", 0).is_err());
+        assert!(Bindings::parse("{MoveUp: \"W\"}", 0).is_err());
+    }
+
+    #[test]
+    fn bindings_players() {
+        let second = Bindings::for_player(1);
+        assert!(second.validate().is_ok());
+        assert_eq!(second.action("w"), Some(Action::MoveUp));
+        assert_eq!(second.action("Left Shift"), Some(Action::Attack));
+        assert!(second.keys(Action::Quit).is_empty());
+        assert_eq!(Bindings::for_player(2).iter().count(), 0);
+
+        // Overrides apply on top of that player's own defaults.
+        let second = Bindings::parse("{Attack: [\"Tab\"]}", 1).unwrap();
+        assert_eq!(second.action("Tab"), Some(Action::Attack));
+        assert_eq!(second.action("D"), Some(Action::MoveRight));
+        assert_eq!(second.action("Space"), None);
+
+        let first = Bindings::default();
+        assert!(Bindings::validate_players(&[first.clone(), second.clone()]).is_ok());
+        let wasd = Bindings::parse(include_str!("../keys/wasd.ron"), 0).unwrap();
+        assert!(Bindings::validate_players(&[wasd, second]).is_err());
+        assert!(Bindings::validate_players(&[first.clone(), first]).is_err());
+    }
+}
@@ -1,130 +1,362 @@
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::EventPump;
+use serde::{Deserialize, Serialize};
 
-use crate::error::Error;
+use crate::bindings::{Action, Bindings};
+use crate::direction::Direction;
+use crate::input::{Axis, Button, ControllerId, InputEvent};
+use crate::point::Point;
 
-#[derive(Debug, Default)]
+// Only the inputs are serialized (e.g. in replays); the key state is
+// specific to the keyboard that produced them.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Control {
-    // Track whether the corresponding keys are currently pressed,
-    // since SDL2 apparently do this for us anymore. Set to true on
-    // KeyDown and false on KeyUp.
-    up_pressed: bool,
-    down_pressed: bool,
-    left_pressed: bool,
-    right_pressed: bool,
-    space_pressed: bool,
+    // Track which keys, buttons and stick directions are currently held
+    // down, so that an action bound to several of them is only released
+    // once all of them are. Key repeats are ignored.
+    #[serde(skip)]
+    held: Vec<Input>,
 
     // Track the current control state for opposing direction keys.
     pub up_down_input: i32,    // -1/0/+1 for up/no input/down
     pub left_right_input: i32, // -1/0/+1 for left/no input/right
 
     // Track the current facing direction.
-    pub facing_input: i32, // 0/1/2/3 for up/left/down/right
+    pub facing_input: Direction,
 
-    // Track the attack status.
+    // Track the attack status. Set on the initial keypress, and cleared
+    // by the caller once a frame has consumed it.
     pub attack_input: bool,
 
+    // Track weapon switching: -1/0/+1 for previous/no input/next. Set on
+    // the keypress, and cleared by the caller like the attack.
+    pub switch_weapon_input: i32,
+
     // Track whether a quit has been requested.
     pub quit_input: bool,
+
+    // Track whether pausing, a quicksave or a quickload has been
+    // requested. These don't affect the simulation, so they're not
+    // serialized.
+    #[serde(skip)]
+    pub pause_input: bool,
+    #[serde(skip)]
+    pub save_input: bool,
+    #[serde(skip)]
+    pub load_input: bool,
 }
 
-pub fn process_input(event_pump: &mut EventPump, control: &mut Control) -> Result<(), Error> {
-    control.attack_input = false; // Attack only on initial keypress.
+// Face the way we're moving, or keep facing the same way once stopped.
+fn update_facing(control: &mut Control) {
+    let input = Point::new(control.left_right_input, control.up_down_input);
+    if let Some(facing) = Direction::from_point(input) {
+        control.facing_input = facing;
+    }
+}
 
-    for event in event_pump.poll_iter() {
-        match event {
-            Event::Quit { .. } => {
-                control.quit_input = true;
-            }
-            Event::KeyDown {
-                keycode: Some(keycode),
-                ..
-            } => match keycode {
-                Keycode::Escape => {
-                    control.quit_input = true;
-                }
-                Keycode::Up => {
-                    if !control.up_pressed {
-                        control.up_down_input = -1;
-                        control.facing_input = 0;
-                    }
-                    control.up_pressed = true;
-                }
-                Keycode::Down => {
-                    if !control.down_pressed {
-                        control.up_down_input = 1;
-                        control.facing_input = 2;
-                    }
-                    control.down_pressed = true;
-                }
-                Keycode::Left => {
-                    if !control.left_pressed {
-                        control.left_right_input = -1;
-                        control.facing_input = 1;
-                    }
-                    control.left_pressed = true;
-                }
-                Keycode::Right => {
-                    if !control.right_pressed {
-                        control.left_right_input = 1;
-                        control.facing_input = 3;
-                    }
-                    control.right_pressed = true;
-                }
-                Keycode::Space => {
-                    control.attack_input = !control.space_pressed;
-                    control.space_pressed = true;
-                }
-                _ => {}
-            },
-            Event::KeyUp {
-                keycode: Some(keycode),
-                ..
-            } => match keycode {
-                Keycode::Up => {
-                    control.up_pressed = false;
-                    if control.up_down_input == -1 {
-                        control.up_down_input = 0;
-                        if control.left_right_input != 0 {
-                            control.facing_input = control.left_right_input + 2;
-                        }
-                    }
-                }
-                Keycode::Down => {
-                    control.down_pressed = false;
-                    if control.up_down_input == 1 {
-                        control.up_down_input = 0;
-                        if control.left_right_input != 0 {
-                            control.facing_input = control.left_right_input + 2;
-                        }
-                    }
-                }
-                Keycode::Left => {
-                    control.left_pressed = false;
-                    if control.left_right_input == -1 {
-                        control.left_right_input = 0;
-                        if control.up_down_input != 0 {
-                            control.facing_input = control.up_down_input + 1;
-                        }
-                    }
-                }
-                Keycode::Right => {
-                    control.right_pressed = false;
-                    if control.left_right_input == 1 {
-                        control.left_right_input = 0;
-                        if control.up_down_input != 0 {
-                            control.facing_input = control.up_down_input + 1;
-                        }
-                    }
-                }
-                Keycode::Space => {
-                    control.space_pressed = false;
+// How far the stick has to be pushed from the center, out of 32767,
+// before it counts. Sticks rarely rest at exactly zero.
+pub const DEAD_ZONE: i16 = 8000;
+
+// Something that can be held down to trigger an action.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Input {
+    Key(String),
+    Button(ControllerId, Button),
+    // Axis pushed past the dead zone, towards -1 or +1.
+    Stick(ControllerId, Axis, i32),
+}
+
+// Which way an axis is pushed, if it's past the dead zone.
+fn stick_direction(value: i16) -> i32 {
+    if value > DEAD_ZONE {
+        1
+    } else if value < -DEAD_ZONE {
+        -1
+    } else {
+        0
+    }
+}
+
+// Controller buttons aren't configurable: the D-pad moves, A attacks,
+// the shoulder buttons switch weapons and Start pauses.
+fn button_action(button: Button) -> Option<Action> {
+    match button {
+        Button::DPadUp => Some(Action::MoveUp),
+        Button::DPadDown => Some(Action::MoveDown),
+        Button::DPadLeft => Some(Action::MoveLeft),
+        Button::DPadRight => Some(Action::MoveRight),
+        Button::A => Some(Action::Attack),
+        Button::LeftShoulder => Some(Action::PreviousWeapon),
+        Button::RightShoulder => Some(Action::NextWeapon),
+        Button::Start => Some(Action::Pause),
+        _ => None,
+    }
+}
+
+fn press(control: &mut Control, action: Action) {
+    match action {
+        Action::MoveUp => {
+            control.up_down_input = -1;
+            update_facing(control);
+        }
+        Action::MoveDown => {
+            control.up_down_input = 1;
+            update_facing(control);
+        }
+        Action::MoveLeft => {
+            control.left_right_input = -1;
+            update_facing(control);
+        }
+        Action::MoveRight => {
+            control.left_right_input = 1;
+            update_facing(control);
+        }
+        Action::Attack => control.attack_input = true,
+        Action::PreviousWeapon => control.switch_weapon_input = -1,
+        Action::NextWeapon => control.switch_weapon_input = 1,
+        Action::Pause => control.pause_input = true,
+        Action::Quit => control.quit_input = true,
+        Action::QuickSave => control.save_input = true,
+        Action::QuickLoad => control.load_input = true,
+    }
+}
+
+// Opposing directions go to whichever was pressed last, and letting go
+// of one doesn't bring back the other.
+fn release(control: &mut Control, action: Action) {
+    match action {
+        Action::MoveUp if control.up_down_input == -1 => {
+            control.up_down_input = 0;
+            update_facing(control);
+        }
+        Action::MoveDown if control.up_down_input == 1 => {
+            control.up_down_input = 0;
+            update_facing(control);
+        }
+        Action::MoveLeft if control.left_right_input == -1 => {
+            control.left_right_input = 0;
+            update_facing(control);
+        }
+        Action::MoveRight if control.left_right_input == 1 => {
+            control.left_right_input = 0;
+            update_facing(control);
+        }
+        _ => {}
+    }
+}
+
+fn input_action(bindings: &Bindings, input: &Input) -> Option<Action> {
+    match *input {
+        Input::Key(ref key) => bindings.action(key),
+        Input::Button(_, button) => button_action(button),
+        Input::Stick(_, Axis::LeftX, -1) => Some(Action::MoveLeft),
+        Input::Stick(_, Axis::LeftX, 1) => Some(Action::MoveRight),
+        Input::Stick(_, Axis::LeftY, -1) => Some(Action::MoveUp),
+        Input::Stick(_, Axis::LeftY, 1) => Some(Action::MoveDown),
+        Input::Stick(..) => None,
+    }
+}
+
+// Whether anything held is bound to the action.
+fn is_held(control: &Control, bindings: &Bindings, action: Action) -> bool {
+    control
+        .held
+        .iter()
+        .any(|input| input_action(bindings, input) == Some(action))
+}
+
+fn input_down(control: &mut Control, bindings: &Bindings, input: Input) {
+    if control.held.contains(&input) {
+        return;
+    }
+    if let Some(action) = input_action(bindings, &input) {
+        if !is_held(control, bindings, action) {
+            press(control, action);
+        }
+    }
+    control.held.push(input);
+}
+
+fn input_up(control: &mut Control, bindings: &Bindings, input: Input) {
+    if !control.held.contains(&input) {
+        return;
+    }
+    control.held.retain(|held| *held != input);
+    if let Some(action) = input_action(bindings, &input) {
+        if !is_held(control, bindings, action) {
+            release(control, action);
+        }
+    }
+}
+
+// Updates the controls for one input event.
+pub fn handle_input(control: &mut Control, bindings: &Bindings, event: &InputEvent) {
+    match *event {
+        InputEvent::KeyDown(ref key) => input_down(control, bindings, Input::Key(key.clone())),
+        InputEvent::KeyUp(ref key) => input_up(control, bindings, Input::Key(key.clone())),
+        InputEvent::ButtonDown(id, button) => {
+            input_down(control, bindings, Input::Button(id, button))
+        }
+        InputEvent::ButtonUp(id, button) => input_up(control, bindings, Input::Button(id, button)),
+        InputEvent::AxisMotion(id, axis, value) => {
+            for &direction in &[-1, 1] {
+                let input = Input::Stick(id, axis, direction);
+                if stick_direction(value) == direction {
+                    input_down(control, bindings, input);
+                } else {
+                    input_up(control, bindings, input);
                 }
-                _ => {}
-            },
-            _ => {}
+            }
+        }
+        InputEvent::ControllerRemoved(id) => {
+            // Let go of everything held on it.
+            let inputs: Vec<_> = control
+                .held
+                .iter()
+                .filter(|input| match **input {
+                    Input::Button(which, _) | Input::Stick(which, _, _) => which == id,
+                    Input::Key(_) => false,
+                })
+                .cloned()
+                .collect();
+            for input in inputs {
+                input_up(control, bindings, input);
+            }
+        }
+        InputEvent::Quit => control.quit_input = true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(control: &mut Control, bindings: &Bindings, events: &[InputEvent]) {
+        for event in events {
+            handle_input(control, bindings, event);
         }
     }
-    Ok(())
+
+    fn down(key: &str) -> InputEvent {
+        InputEvent::KeyDown(key.to_string())
+    }
+
+    fn up(key: &str) -> InputEvent {
+        InputEvent::KeyUp(key.to_string())
+    }
+
+    fn inputs(control: &Control) -> (i32, i32, Direction) {
+        (
+            control.left_right_input,
+            control.up_down_input,
+            control.facing_input,
+        )
+    }
+
+    #[test]
+    fn control_opposing_keys() {
+        let bindings = Bindings::default();
+        let mut control = Control::default();
+
+        // Hold left, press up, release up.
+        run(&mut control, &bindings, &[down("Left")]);
+        assert_eq!(inputs(&control), (-1, 0, Direction::Left));
+        run(&mut control, &bindings, &[down("Up")]);
+        assert_eq!(inputs(&control), (-1, -1, Direction::UpLeft));
+        run(&mut control, &bindings, &[up("Up")]);
+        assert_eq!(inputs(&control), (-1, 0, Direction::Left));
+
+        // The last of two opposing keys wins, and letting go of it
+        // doesn't bring back the other.
+        run(&mut control, &bindings, &[down("Right")]);
+        assert_eq!(inputs(&control), (1, 0, Direction::Right));
+        run(&mut control, &bindings, &[up("Right")]);
+        assert_eq!(inputs(&control), (0, 0, Direction::Right));
+        run(&mut control, &bindings, &[up("Left")]);
+        assert_eq!(inputs(&control), (0, 0, Direction::Right));
+
+        // Letting go of a diagonal one key at a time ends up facing
+        // whichever was held longer.
+        run(
+            &mut control,
+            &bindings,
+            &[down("Down"), down("Left"), up("Down"), up("Left")],
+        );
+        assert_eq!(inputs(&control), (0, 0, Direction::Left));
+        run(&mut control, &bindings, &[down("Down"), down("Left")]);
+        assert_eq!(inputs(&control), (-1, 1, Direction::DownLeft));
+    }
+
+    #[test]
+    fn control_keys() {
+        let bindings = Bindings::parse(include_str!("../keys/wasd.ron"), 0).unwrap();
+        let mut control = Control::default();
+
+        // Held on two keys, so still held after letting go of one.
+        run(&mut control, &bindings, &[down("Up"), down("W"), up("Up")]);
+        assert_eq!(control.up_down_input, -1);
+        run(&mut control, &bindings, &[up("W")]);
+        assert_eq!(control.up_down_input, 0);
+        assert_eq!(control.facing_input, Direction::Up);
+
+        // Repeated presses of a held key don't count again.
+        run(&mut control, &bindings, &[down("Space")]);
+        assert!(control.attack_input);
+        control.attack_input = false;
+        run(&mut control, &bindings, &[down("Space")]);
+        assert!(!control.attack_input);
+
+        run(
+            &mut control,
+            &bindings,
+            &[down("Z"), down("Q"), InputEvent::Quit],
+        );
+        assert_eq!(control.switch_weapon_input, -1);
+        assert!(control.quit_input);
+    }
+
+    #[test]
+    fn control_gamepad() {
+        let bindings = Bindings::default();
+        let mut control = Control::default();
+        let stick = |axis, value| InputEvent::AxisMotion(0, axis, value);
+
+        // Small pushes are inside the dead zone.
+        run(&mut control, &bindings, &[stick(Axis::LeftX, -DEAD_ZONE)]);
+        assert_eq!(control.left_right_input, 0);
+        run(
+            &mut control,
+            &bindings,
+            &[stick(Axis::LeftX, -20000), stick(Axis::LeftY, 20000)],
+        );
+        assert_eq!(inputs(&control), (-1, 1, Direction::DownLeft));
+        run(&mut control, &bindings, &[stick(Axis::LeftY, 100)]);
+        assert_eq!(inputs(&control), (-1, 0, Direction::Left));
+
+        // The stick and the keyboard both hold left.
+        run(
+            &mut control,
+            &bindings,
+            &[down("Left"), stick(Axis::LeftX, 0)],
+        );
+        assert_eq!(control.left_right_input, -1);
+        run(&mut control, &bindings, &[up("Left")]);
+        assert_eq!(control.left_right_input, 0);
+
+        // Buttons, and unplugging lets go of everything on a controller.
+        run(
+            &mut control,
+            &bindings,
+            &[
+                InputEvent::ButtonDown(1, Button::A),
+                InputEvent::ButtonDown(1, Button::DPadRight),
+                InputEvent::ButtonDown(2, Button::Start),
+            ],
+        );
+        assert!(control.attack_input);
+        assert!(control.pause_input);
+        assert_eq!(control.left_right_input, 1);
+        run(&mut control, &bindings, &[InputEvent::ControllerRemoved(1)]);
+        assert_eq!(control.left_right_input, 0);
+        assert_eq!(control.held, vec![Input::Button(2, Button::Start)]);
+    }
 }
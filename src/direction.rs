@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+
+use crate::point::Point;
+
+// The eight ways an entity can face, counterclockwise from up. Screen
+// y points down, so up is (0, -1).
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum Direction {
+    #[default]
+    Up,
+    UpLeft,
+    Left,
+    DownLeft,
+    Down,
+    DownRight,
+    Right,
+    UpRight,
+}
+
+impl Direction {
+    // In order, 45 degrees apart.
+    pub const ALL: [Direction; 8] = [
+        Direction::Up,
+        Direction::UpLeft,
+        Direction::Left,
+        Direction::DownLeft,
+        Direction::Down,
+        Direction::DownRight,
+        Direction::Right,
+        Direction::UpRight,
+    ];
+
+    // The direction closest to the given vector, going by sign only. None
+    // for the zero vector.
+    pub fn from_point(p: Point) -> Option<Direction> {
+        let step = Point::new(p.x.signum(), p.y.signum());
+        Direction::ALL
+            .iter()
+            .copied()
+            .find(|&dir| dir.to_point() == step)
+    }
+
+    // Unit step in this direction, diagonals included.
+    pub fn to_point(self) -> Point {
+        match self {
+            Direction::Up => Point::new(0, -1),
+            Direction::UpLeft => Point::new(-1, -1),
+            Direction::Left => Point::new(-1, 0),
+            Direction::DownLeft => Point::new(-1, 1),
+            Direction::Down => Point::new(0, 1),
+            Direction::DownRight => Point::new(1, 1),
+            Direction::Right => Point::new(1, 0),
+            Direction::UpRight => Point::new(1, -1),
+        }
+    }
+
+    // Degrees counterclockwise from up, as taken by Point::rotate and
+    // Rect::rotate to turn something drawn facing up this way.
+    pub fn angle(self) -> i32 {
+        self as i32 * 45
+    }
+
+    pub fn is_diagonal(self) -> bool {
+        let step = self.to_point();
+        step.x != 0 && step.y != 0
+    }
+
+    // The direction the given number of 45 degree steps counterclockwise
+    // from this one, or clockwise for negative steps.
+    pub fn rotate(self, steps: i32) -> Direction {
+        Direction::ALL[(self as i32 + steps).rem_euclid(8) as usize]
+    }
+
+    pub fn turn_left(self) -> Direction {
+        self.rotate(2)
+    }
+
+    pub fn turn_right(self) -> Direction {
+        self.rotate(-2)
+    }
+}
+
+impl From<Direction> for Point {
+    fn from(dir: Direction) -> Point {
+        dir.to_point()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direction_points() {
+        for &dir in &Direction::ALL {
+            let step = dir.to_point();
+            assert_eq!(Direction::from_point(step), Some(dir));
+            assert_eq!(Direction::from_point(step * 5), Some(dir));
+            assert_eq!(dir.is_diagonal(), dir.angle() % 90 != 0);
+
+            // Rotating the step by the angle between two directions
+            // gives the other one.
+            let next = dir.rotate(1);
+            let rotated = (step * 10).rotate(Point::new(0, 0), next.angle() - dir.angle());
+            assert_eq!(Direction::from_point(rotated), Some(next));
+            assert_eq!(
+                Point::new(0, -10).rotate(Point::new(0, 0), dir.angle()) / 7,
+                step
+            );
+        }
+        assert_eq!(
+            Direction::from_point(Point::new(3, -1)),
+            Some(Direction::UpRight)
+        );
+        assert_eq!(Direction::from_point(Point::new(0, 0)), None);
+    }
+
+    #[test]
+    fn direction_rotate() {
+        assert_eq!(Direction::Up.turn_left(), Direction::Left);
+        assert_eq!(Direction::Up.turn_right(), Direction::Right);
+        assert_eq!(Direction::UpRight.rotate(1), Direction::Up);
+        assert_eq!(Direction::Up.rotate(-1), Direction::UpRight);
+        assert_eq!(Direction::Down.rotate(12), Direction::Up);
+        assert_eq!(Direction::Right.angle(), 270);
+    }
+}
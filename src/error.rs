@@ -3,6 +3,13 @@ pub enum Error {
     SdlError(String),
     WindowError(sdl2::video::WindowBuildError),
     CanvasError(sdl2::IntegerOrSdlError),
+    ArgumentError(String),
+    IoError(std::io::Error),
+    SerializeError(ron::Error),
+    DeserializeError(ron::error::SpannedError),
+    ImageError(String),
+    LevelError(String),
+    ConfigError(String),
 }
 
 impl From<String> for Error {
@@ -22,3 +29,21 @@ impl From<sdl2::IntegerOrSdlError> for Error {
         Error::CanvasError(error)
     }
 }
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Error {
+        Error::IoError(error)
+    }
+}
+
+impl From<ron::Error> for Error {
+    fn from(error: ron::Error) -> Error {
+        Error::SerializeError(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for Error {
+    fn from(error: ron::error::SpannedError) -> Error {
+        Error::DeserializeError(error)
+    }
+}
@@ -0,0 +1,43 @@
+use crate::state::{EntityId, EntityKind};
+
+// Things that happened during a frame which are otherwise hard to
+// observe from the state alone (e.g. an entity vanishing).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    EntitySpawned { id: EntityId, kind: EntityKind },
+    MonsterHit { monster: EntityId, player: EntityId },
+    MonsterKilled { monster: EntityId, player: EntityId },
+    PowerCollected { power: EntityId, player: EntityId },
+    PlayerDied { player: EntityId, monster: EntityId },
+    WeaponSwitched { player: EntityId, weapon: usize },
+}
+
+// Events emitted by the systems during the current frame. The queue is
+// cleared at the start of each step, so after a step it holds exactly
+// what happened in that step, for rendering, audio, logging, etc.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Events {
+    events: Vec<Event>,
+}
+
+impl Events {
+    pub fn emit(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Event> {
+        self.events.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+}
@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+// Bits after the binary point, so positions are in 1/256ths of a pixel.
+pub const FRAC_BITS: u32 = 8;
+
+// A signed fixed-point number. Unlike floats, arithmetic on these gives
+// the same result on every machine, so the simulation can use them and
+// stay deterministic.
+#[derive(Copy, Clone, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(transparent)]
+pub struct Fixed(i32);
+
+// num / den in raw fixed-point units, rounded towards negative infinity.
+const fn ratio(num: i64, den: i64) -> i32 {
+    (num << FRAC_BITS).div_euclid(den) as i32
+}
+
+impl Fixed {
+    pub const ZERO: Fixed = Fixed(0);
+    pub const ONE: Fixed = Fixed(1 << FRAC_BITS);
+
+    pub const fn from_raw(raw: i32) -> Fixed {
+        Fixed(raw)
+    }
+
+    pub const fn from_int(x: i32) -> Fixed {
+        Fixed(x << FRAC_BITS)
+    }
+
+    pub const fn from_ratio(num: i32, den: i32) -> Fixed {
+        Fixed(ratio(num as i64, den as i64))
+    }
+
+    pub fn raw(self) -> i32 {
+        self.0
+    }
+
+    // The whole pixel this falls in.
+    pub fn floor(self) -> i32 {
+        self.0 >> FRAC_BITS
+    }
+
+    pub fn ceil(self) -> i32 {
+        (self.0 + (1 << FRAC_BITS) - 1) >> FRAC_BITS
+    }
+
+    pub fn round(self) -> i32 {
+        (self.0 + (1 << (FRAC_BITS - 1))) >> FRAC_BITS
+    }
+
+    pub fn abs(self) -> Fixed {
+        Fixed(self.0.abs())
+    }
+
+    // Moves towards target by at most step.
+    pub fn approach(self, target: Fixed, step: Fixed) -> Fixed {
+        if self < target {
+            std::cmp::min(self + step, target)
+        } else {
+            std::cmp::max(self - step, target)
+        }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / (1 << FRAC_BITS) as f64
+    }
+}
+
+impl fmt::Debug for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_f64())
+    }
+}
+
+impl From<i32> for Fixed {
+    fn from(x: i32) -> Fixed {
+        Fixed::from_int(x)
+    }
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+
+    fn add(self, other: Fixed) -> Fixed {
+        Fixed(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, other: Fixed) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+
+    fn sub(self, other: Fixed) -> Fixed {
+        Fixed(self.0 - other.0)
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, other: Fixed) {
+        self.0 -= other.0;
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+
+    fn neg(self) -> Fixed {
+        Fixed(-self.0)
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Fixed;
+
+    fn mul(self, other: Fixed) -> Fixed {
+        Fixed(((self.0 as i64 * other.0 as i64) >> FRAC_BITS) as i32)
+    }
+}
+
+impl Mul<i32> for Fixed {
+    type Output = Fixed;
+
+    fn mul(self, other: i32) -> Fixed {
+        Fixed(self.0 * other)
+    }
+}
+
+impl Div for Fixed {
+    type Output = Fixed;
+
+    fn div(self, other: Fixed) -> Fixed {
+        Fixed(ratio(self.0 as i64, other.0 as i64))
+    }
+}
+
+impl Div<i32> for Fixed {
+    type Output = Fixed;
+
+    fn div(self, other: i32) -> Fixed {
+        Fixed(self.0.div_euclid(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_conversions() {
+        assert_eq!(Fixed::from_int(3).floor(), 3);
+        assert_eq!(Fixed::from(-3).floor(), -3);
+        assert_eq!(Fixed::from_ratio(3, 2).floor(), 1);
+        assert_eq!(Fixed::from_ratio(3, 2).round(), 2);
+        assert_eq!(Fixed::from_ratio(3, 2).ceil(), 2);
+        assert_eq!(Fixed::from_int(3).ceil(), 3);
+        assert_eq!(Fixed::from_ratio(-1, 2).floor(), -1);
+        assert_eq!(Fixed::from_ratio(-1, 4).round(), 0);
+        assert_eq!(Fixed::from_ratio(-1, 1000), Fixed::from_raw(-1));
+        assert_eq!(Fixed::from_ratio(1, 256), Fixed::from_raw(1));
+        assert_eq!(Fixed::from_ratio(3, 4).to_f64(), 0.75);
+    }
+
+    #[test]
+    fn fixed_arithmetic() {
+        let a = Fixed::from_ratio(3, 2);
+        let b = Fixed::from_ratio(1, 4);
+        assert_eq!(a + b, Fixed::from_ratio(7, 4));
+        assert_eq!(b - a, Fixed::from_ratio(-5, 4));
+        assert_eq!(a * b, Fixed::from_ratio(3, 8));
+        assert_eq!(a * 2, Fixed::from_int(3));
+        assert_eq!(a / 3, Fixed::from_ratio(1, 2));
+        assert_eq!(a / b, Fixed::from_int(6));
+        assert_eq!(-a, Fixed::from_ratio(-3, 2));
+        assert_eq!((-a).abs(), a);
+
+        // Sub-pixel steps add up to whole pixels exactly.
+        let mut x = Fixed::ZERO;
+        for _ in 0..8 {
+            x += Fixed::from_ratio(3, 2);
+        }
+        assert_eq!(x.floor(), 12);
+    }
+
+    #[test]
+    fn fixed_approach() {
+        let step = Fixed::from_ratio(1, 2);
+        assert_eq!(Fixed::ZERO.approach(Fixed::ONE, step), step);
+        assert_eq!(step.approach(Fixed::ONE, step), Fixed::ONE);
+        assert_eq!(Fixed::ONE.approach(Fixed::ONE, step), Fixed::ONE);
+        assert_eq!(
+            Fixed::ZERO.approach(-Fixed::ONE, Fixed::from(2)),
+            -Fixed::ONE
+        );
+    }
+}
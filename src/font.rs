@@ -0,0 +1,86 @@
+// A tiny bitmap font for drawing text with nothing but filled
+// rectangles: 3x5 pixels per character, upper case letters, digits and
+// a little punctuation.
+
+pub const GLYPH_WIDTH: i32 = 3;
+pub const GLYPH_HEIGHT: i32 = 5;
+
+// Blank columns between characters.
+pub const SPACING: i32 = 1;
+
+// The rows of a character from the top, leftmost pixel in the highest
+// of the three bits. Lower case is drawn as upper case, and anything
+// else the font doesn't have as a space.
+pub fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        _ => [0; 5],
+    }
+}
+
+// Size in pixels of a line of text drawn at the given scale.
+pub fn text_size(text: &str, scale: i32) -> (i32, i32) {
+    let chars = text.chars().count() as i32;
+    let width = (chars * (GLYPH_WIDTH + SPACING) - SPACING).max(0);
+    (width * scale, GLYPH_HEIGHT * scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn font_glyphs() {
+        assert_eq!(glyph('a'), glyph('A'));
+        assert_eq!(glyph(' '), [0; 5]);
+        assert_eq!(glyph('~'), [0; 5]);
+        for c in "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ:-.!".chars() {
+            let rows = glyph(c);
+            assert!(rows.iter().any(|&row| row != 0), "{} is blank", c);
+            assert!(rows.iter().all(|&row| row < 1 << GLYPH_WIDTH));
+        }
+
+        assert_eq!(text_size("", 2), (0, 10));
+        assert_eq!(text_size("A", 1), (3, 5));
+        assert_eq!(text_size("GAME OVER", 2), (70, 10));
+    }
+}
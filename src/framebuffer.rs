@@ -0,0 +1,224 @@
+use std::cmp::{max, min};
+use std::fs;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::rect::Rect;
+use crate::render::{Color, Renderer};
+
+// Software renderer drawing into an RGBA image in memory, for machines
+// without a display (tests, tools, servers).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>, // RGBA, row-major
+    color: Color,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        let mut fb = Framebuffer {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+            color: Color::rgb(0, 0, 0),
+        };
+        fb.clear();
+        fb
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        let i = ((y * self.width + x) * 4) as usize;
+        let p = &self.pixels[i..i + 4];
+        Color {
+            r: p[0],
+            g: p[1],
+            b: p[2],
+            a: p[3],
+        }
+    }
+
+    // Number of pixels where any channel differs by more than
+    // tolerance, or None if the sizes don't match.
+    pub fn diff(&self, other: &Framebuffer, tolerance: u8) -> Option<usize> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return None;
+        }
+        let count = self
+            .pixels
+            .chunks(4)
+            .zip(other.pixels.chunks(4))
+            .filter(|(p, q)| p.iter().zip(q.iter()).any(|(a, b)| a.abs_diff(*b) > tolerance))
+            .count();
+        Some(count)
+    }
+
+    // Images are stored as binary PPM (P6): trivial to read and write,
+    // and viewable with most image tools. Alpha is dropped.
+    pub fn write_ppm<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut data = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        for p in self.pixels.chunks(4) {
+            data.extend_from_slice(&p[..3]);
+        }
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    pub fn read_ppm<P: AsRef<Path>>(path: P) -> Result<Framebuffer, Error> {
+        let data = fs::read(path)?;
+
+        // The header is four whitespace-separated fields, followed by a
+        // single whitespace character and then the pixel data.
+        let mut fields = Vec::new();
+        let mut i = 0;
+        while fields.len() < 4 {
+            while i < data.len() && data[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            let start = i;
+            while i < data.len() && !data[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            if start == i {
+                return Err(Error::ImageError("truncated PPM header".to_string()));
+            }
+            fields.push(String::from_utf8_lossy(&data[start..i]).into_owned());
+        }
+        i += 1;
+
+        let number = |s: &str| {
+            s.parse::<u32>()
+                .map_err(|_| Error::ImageError(format!("invalid PPM header field: {}", s)))
+        };
+        if fields[0] != "P6" || number(&fields[3])? != 255 {
+            return Err(Error::ImageError(
+                "only 8-bit binary PPM (P6) is supported".to_string(),
+            ));
+        }
+        let (width, height) = (number(&fields[1])?, number(&fields[2])?);
+
+        // Too big to hold as RGBA, never mind the size of the file.
+        let size = width
+            .checked_mul(height)
+            .filter(|size| size.checked_mul(4).is_some())
+            .ok_or_else(|| Error::ImageError("PPM is too large".to_string()))?;
+
+        let rgb = data.get(i..).unwrap_or(&[]);
+        if rgb.len() as u64 != size as u64 * 3 {
+            return Err(Error::ImageError("PPM data has the wrong size".to_string()));
+        }
+        let mut fb = Framebuffer::new(width, height);
+        for (p, q) in fb.pixels.chunks_mut(4).zip(rgb.chunks(3)) {
+            p[..3].copy_from_slice(q);
+        }
+        Ok(fb)
+    }
+}
+
+impl Renderer for Framebuffer {
+    fn set_color(&mut self, color: Color) {
+        self.color = color;
+    }
+
+    fn clear(&mut self) {
+        let c = self.color;
+        for p in self.pixels.chunks_mut(4) {
+            p.copy_from_slice(&[c.r, c.g, c.b, c.a]);
+        }
+    }
+
+    fn fill_rect(&mut self, r: Rect) -> Result<(), Error> {
+        let c = self.color;
+        let (w, h) = (self.width as i32, self.height as i32);
+        let x0 = min(max(r.lo.x, 0), w) as usize;
+        let x1 = min(max(r.hi.x, 0), w) as usize;
+        let y0 = min(max(r.lo.y, 0), h) as usize;
+        let y1 = min(max(r.hi.y, 0), h) as usize;
+        for y in y0..y1 {
+            let row = y * self.width as usize;
+            for x in x0..x1 {
+                let i = (row + x) * 4;
+                self.pixels[i..i + 4].copy_from_slice(&[c.r, c.g, c.b, c.a]);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn framebuffer_clear() {
+        let mut fb = Framebuffer::new(4, 3);
+        fb.set_color(Color::rgb(1, 2, 3));
+        fb.clear();
+        assert_eq!(fb.pixel(0, 0), Color::rgb(1, 2, 3));
+        assert_eq!(fb.pixel(3, 2), Color::rgb(1, 2, 3));
+    }
+
+    #[test]
+    fn framebuffer_ppm() {
+        let mut fb = Framebuffer::new(5, 4);
+        fb.set_color(Color::rgb(10, 20, 30));
+        fb.fill_rect(((1, 1), (3, 4)).into()).unwrap();
+
+        let path = std::env::temp_dir().join("game_framebuffer_ppm.ppm");
+        fb.write_ppm(&path).unwrap();
+        let loaded = Framebuffer::read_ppm(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.pixels(), fb.pixels());
+        assert_eq!((loaded.width(), loaded.height()), (5, 4));
+
+        // Sizes that don't fit are an error rather than an overflow.
+        fs::write(&path, "P6 65536 65536 255\n").unwrap();
+        let result = Framebuffer::read_ppm(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(Error::ImageError(_))));
+    }
+
+    #[test]
+    fn framebuffer_diff() {
+        let fb1 = Framebuffer::new(4, 4);
+        let mut fb2 = fb1.clone();
+        assert_eq!(fb1.diff(&fb2, 0), Some(0));
+
+        fb2.set_color(Color::rgb(3, 0, 0));
+        fb2.fill_rect(((0, 0), (2, 1)).into()).unwrap();
+        assert_eq!(fb1.diff(&fb2, 0), Some(2));
+        assert_eq!(fb1.diff(&fb2, 3), Some(0));
+        assert_eq!(fb1.diff(&Framebuffer::new(4, 5), 0), None);
+    }
+
+    #[test]
+    fn framebuffer_fill_rect() {
+        let mut fb = Framebuffer::new(8, 8);
+        fb.set_color(Color::rgb(255, 0, 0));
+        fb.fill_rect(((2, 3), (4, 5)).into()).unwrap();
+        assert_eq!(fb.pixel(1, 3), Color::rgb(0, 0, 0));
+        assert_eq!(fb.pixel(2, 3), Color::rgb(255, 0, 0));
+        assert_eq!(fb.pixel(3, 4), Color::rgb(255, 0, 0));
+        assert_eq!(fb.pixel(4, 4), Color::rgb(0, 0, 0)); // hi is exclusive
+        assert_eq!(fb.pixel(3, 5), Color::rgb(0, 0, 0));
+
+        // Rects are clipped to the framebuffer.
+        fb.set_color(Color::rgb(0, 255, 0));
+        fb.fill_rect(((-5, -5), (1, 100)).into()).unwrap();
+        assert_eq!(fb.pixel(0, 7), Color::rgb(0, 255, 0));
+        assert_eq!(fb.pixel(1, 7), Color::rgb(0, 0, 0));
+    }
+}
@@ -0,0 +1,47 @@
+// Platform-neutral input, as fed to control::handle_input. See
+// sdl_input for where it comes from when playing.
+
+// Identifies a game controller for as long as it stays plugged in.
+pub type ControllerId = i32;
+
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Button {
+    A,
+    B,
+    X,
+    Y,
+    Back,
+    Guide,
+    Start,
+    LeftStick,
+    RightStick,
+    LeftShoulder,
+    RightShoulder,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Axis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    TriggerLeft,
+    TriggerRight,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum InputEvent {
+    // Keys by name, as in the key bindings. Repeats aren't sent.
+    KeyDown(String),
+    KeyUp(String),
+    ButtonDown(ControllerId, Button),
+    ButtonUp(ControllerId, Button),
+    // Position from -32768 to 32767, negative being left or up.
+    AxisMotion(ControllerId, Axis, i16),
+    ControllerRemoved(ControllerId),
+    Quit,
+}
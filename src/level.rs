@@ -0,0 +1,218 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::direction::Direction;
+use crate::error::Error;
+use crate::point::Point;
+use crate::rect::Rect;
+use crate::tilemap::TileMap;
+use crate::wave::{default_waves, validate_waves, SpawnPoint, Wave};
+use crate::weapon::{default_weapons, validate_weapons, Weapon};
+
+// Size of the entities placed by a level.
+pub const ENTITY_SIZE: i32 = 32;
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MonsterPlacement {
+    pub position: Point,
+    pub facing: Direction,
+}
+
+// Initial layout of a game: the arena, its walls, the entities in it,
+// the monster waves to come and the player's weapons. See
+// levels/default.ron for the format.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Level {
+    pub width: i32,
+    pub height: i32,
+    pub player: Point,
+    // Where the second player on starts in co-op, in order. Any more
+    // players than this start where the first one does.
+    #[serde(default)]
+    pub co_op_starts: Vec<Point>,
+    #[serde(default)]
+    pub monsters: Vec<MonsterPlacement>,
+    #[serde(default)]
+    pub powers: Vec<Point>,
+    #[serde(default = "default_tile_size")]
+    pub tile_size: i32,
+    #[serde(default)]
+    pub tiles: Vec<String>,
+    #[serde(default = "default_waves")]
+    pub waves: Vec<Wave>,
+    #[serde(default = "default_weapons")]
+    pub weapons: Vec<Weapon>,
+}
+
+fn default_tile_size() -> i32 {
+    ENTITY_SIZE
+}
+
+impl Level {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Level, Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        Level::parse(&text)
+            .map_err(|e| Error::LevelError(format!("{}: {}", path.display(), level_message(e))))
+    }
+
+    pub fn parse(text: &str) -> Result<Level, Error> {
+        let level: Level = ron::from_str(text)?;
+        level.validate()?;
+        Ok(level)
+    }
+
+    pub fn arena(&self) -> Rect {
+        Rect::new(Point::new(0, 0), Point::new(self.width, self.height))
+    }
+
+    pub fn tile_map(&self) -> Result<TileMap, Error> {
+        if self.tile_size <= 0 {
+            return Err(Error::LevelError(format!(
+                "tile size must be positive, got {}",
+                self.tile_size
+            )));
+        }
+        let size = self.tile_size;
+        TileMap::parse(
+            &self.tiles,
+            (self.width + size - 1) / size,
+            (self.height + size - 1) / size,
+            size,
+        )
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.width < ENTITY_SIZE || self.height < ENTITY_SIZE {
+            return Err(Error::LevelError(format!(
+                "arena must be at least {}x{}, got {}x{}",
+                ENTITY_SIZE, ENTITY_SIZE, self.width, self.height
+            )));
+        }
+
+        // Same bounds that movement is clamped to.
+        let bounds = self.arena().grow(-ENTITY_SIZE);
+        let tiles = self.tile_map()?;
+        let check = |what: String, p: Point| {
+            if bounds.clamp(p) != p {
+                return Err(Error::LevelError(format!(
+                    "{} at ({}, {}) is outside the arena",
+                    what, p.x, p.y
+                )));
+            }
+            if tiles.is_blocked(Rect::new_with_size(p.x, p.y, ENTITY_SIZE, ENTITY_SIZE)) {
+                return Err(Error::LevelError(format!(
+                    "{} at ({}, {}) is inside a wall",
+                    what, p.x, p.y
+                )));
+            }
+            Ok(())
+        };
+
+        check("player".to_string(), self.player)?;
+        for (i, start) in self.co_op_starts.iter().enumerate() {
+            check(format!("player {}", i + 2), *start)?;
+        }
+        for (i, monster) in self.monsters.iter().enumerate() {
+            check(format!("monster {}", i), monster.position)?;
+        }
+        for (i, power) in self.powers.iter().enumerate() {
+            check(format!("power-up {}", i), *power)?;
+        }
+        validate_weapons(&self.weapons)?;
+        validate_waves(&self.waves)?;
+        for (i, wave) in self.waves.iter().enumerate() {
+            if let SpawnPoint::At(p) = wave.spawn {
+                check(format!("wave {}", i), p)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Level {
+    fn default() -> Level {
+        Level::parse(include_str!("../levels/default.ron")).expect("default level is valid")
+    }
+}
+
+fn level_message(error: Error) -> String {
+    match error {
+        Error::LevelError(message) => message,
+        Error::DeserializeError(error) => error.to_string(),
+        error => format!("{:?}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_default() {
+        let level = Level::default();
+        assert_eq!(level.arena(), ((0, 0), (800, 600)).into());
+        assert_eq!(level.player, Point::new(400, 300));
+        assert_eq!(level.co_op_starts, vec![Point::new(360, 300)]);
+        assert_eq!(level.monsters.len(), 4);
+        assert!(level.powers.is_empty());
+        assert_eq!(level.waves, default_waves());
+        assert_eq!(level.weapons, default_weapons());
+    }
+
+    #[test]
+    fn level_walls() {
+        let level = Level::parse(include_str!("../levels/walls.ron")).unwrap();
+        let tiles = level.tile_map().unwrap();
+        assert_eq!((tiles.width, tiles.height), (25, 19));
+        assert!(tiles.walls().count() > 0);
+    }
+
+    #[test]
+    fn level_parse_errors() {
+        // Syntax errors.
+        assert!(Level::parse("(width: 800").is_err());
+        assert!(Level::parse("(width: 800, height: 600, player: (x: 1, y: 2), bogus: 1)").is_err());
+
+        // Semantic errors.
+        let base = "width: 100, height: 100";
+        assert!(Level::parse(&format!("({}, player: (x: 10, y: 10))", base)).is_ok());
+        assert!(Level::parse(&format!("({}, player: (x: 90, y: 10))", base)).is_err());
+        assert!(Level::parse("(width: 10, height: 10, player: (x: 0, y: 0))").is_err());
+        assert!(Level::parse(&format!(
+            "({}, player: (x: 0, y: 0), monsters: [(position: (x: 0, y: 0), facing: Sideways)])",
+            base
+        ))
+        .is_err());
+        assert!(Level::parse(&format!(
+            "({}, player: (x: 0, y: 0), powers: [(x: -1, y: 0)])",
+            base
+        ))
+        .is_err());
+        assert!(Level::parse(&format!(
+            "({}, player: (x: 0, y: 0), co_op_starts: [(x: 10, y: 10), (x: 80, y: 0)])",
+            base
+        ))
+        .is_err());
+        assert!(Level::parse(&format!(
+            "({}, player: (x: 0, y: 0), waves: [(trigger: Frame(0), spawn: At((x: 99, y: 0)))])",
+            base
+        ))
+        .is_err());
+        assert!(
+            Level::parse(&format!("({}, player: (x: 10, y: 10), tile_size: 0)", base)).is_err()
+        );
+        assert!(Level::parse(&format!(
+            "({}, player: (x: 10, y: 10), tile_size: 10, tiles: [\"#\"])",
+            base
+        ))
+        .is_ok());
+        assert!(Level::parse(&format!(
+            "({}, player: (x: 10, y: 10), tile_size: 10, tiles: [\".\", \".#\"])",
+            base
+        ))
+        .is_err());
+    }
+}
@@ -1,5 +1,27 @@
+pub mod bindings;
 pub mod control;
+pub mod direction;
 pub mod error;
+pub mod event;
+pub mod fixed;
+pub mod font;
+pub mod framebuffer;
+pub mod input;
+pub mod level;
+pub mod lockstep;
+pub mod net;
 pub mod point;
 pub mod rect;
+pub mod render;
+pub mod replay;
+pub mod rng;
+pub mod rollback;
+pub mod scene;
+pub mod sdl_input;
+pub mod sim;
+pub mod spatial;
 pub mod state;
+pub mod tilemap;
+pub mod timestep;
+pub mod wave;
+pub mod weapon;
@@ -0,0 +1,195 @@
+use std::time::Duration;
+
+use crate::control::Control;
+use crate::error::Error;
+use crate::net::{InputSync, Transport};
+
+// Networked play in deterministic lockstep. Every peer runs the whole
+// simulation, and a frame only runs once the controls of every player
+// for it have arrived, so all peers step through exactly the same
+// frames with the same controls.
+//
+// Local input is scheduled the given number of frames ahead, which
+// gives it that long to reach the other peers before it's needed. The
+// first frames run with no input from anyone.
+pub struct Lockstep<T> {
+    sync: InputSync<T>,
+    delay: u64,
+    frame: u64,
+}
+
+impl<T: Transport> Lockstep<T> {
+    // Takes a transport to each other peer, with the player it's for.
+    pub fn new(local: usize, players: usize, delay: u64, peers: Vec<(usize, T)>) -> Lockstep<T> {
+        Lockstep {
+            sync: InputSync::new(local, players, delay, peers),
+            delay,
+            frame: 0,
+        }
+    }
+
+    pub fn local_player(&self) -> usize {
+        self.sync.local_player()
+    }
+
+    // The next frame to run.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // Whether the local player's input is wanted. It stops being wanted
+    // once it's the delay ahead of the frames run, so when waiting on the
+    // other peers the input isn't taken and keeps until it is.
+    pub fn needs_input(&self) -> bool {
+        self.sync.len(self.local_player()) <= self.frame + self.delay
+    }
+
+    pub fn add_local_input(&mut self, control: &Control) {
+        self.sync.add_local_input(control);
+    }
+
+    // Whether the other peers have all the input we've added, so it's
+    // safe to leave once we've added a quit.
+    pub fn is_acked(&self) -> bool {
+        self.sync.is_acked()
+    }
+
+    // How long since we've heard from the quietest peer.
+    pub fn silence(&self) -> Duration {
+        self.sync.silence()
+    }
+
+    // Sends our unacknowledged input to every peer, and takes in what
+    // they've sent us. Call this regularly, even while waiting.
+    pub fn poll(&mut self) -> Result<(), Error> {
+        self.sync.send(None)?;
+        self.sync.receive()?;
+        Ok(())
+    }
+
+    // The controls of every player for the next frame, if they've all
+    // arrived, moving on to the frame after.
+    pub fn next_frame(&mut self) -> Option<Vec<Control>> {
+        let controls = self.sync.frame(self.frame)?;
+        self.frame += 1;
+        Some(controls)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::direction::Direction;
+    use crate::level::Level;
+    use crate::net::{LossyTransport, UdpTransport};
+    use crate::sim::World;
+    use std::net::UdpSocket;
+
+    type Session = Lockstep<LossyTransport<UdpTransport>>;
+
+    // Two peers on loopback sockets, each losing and reordering some of
+    // what it sends.
+    fn sessions(delay: u64, loss: i32, reorder: i32) -> (Session, Session) {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (a_addr, b_addr) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+        let a = UdpTransport::new(a, b_addr).unwrap();
+        let b = UdpTransport::new(b, a_addr).unwrap();
+        (
+            Lockstep::new(
+                0,
+                2,
+                delay,
+                vec![(1, LossyTransport::new(a, 1, loss, reorder))],
+            ),
+            Lockstep::new(
+                1,
+                2,
+                delay,
+                vec![(0, LossyTransport::new(b, 2, loss, reorder))],
+            ),
+        )
+    }
+
+    // Some input that changes from frame to frame, differently for each
+    // player.
+    fn scripted(player: usize, frame: usize) -> Control {
+        let frame = frame + player * 23;
+        let mut control = Control::default();
+        control.left_right_input = [1, 0, -1, 0][(frame / 20) % 4];
+        control.up_down_input = [0, 1, 0, -1][(frame / 30) % 4];
+        control.facing_input = Direction::ALL[(frame / 15) % 8];
+        control.attack_input = frame.is_multiple_of(10);
+        control
+    }
+
+    // Runs both peers until they've stepped the given number of frames,
+    // returning their worlds and the controls each frame ran with.
+    fn run(sessions: &mut [Session; 2], frames: usize) -> Vec<(World, Vec<Vec<Control>>)> {
+        let mut peers: Vec<_> = (0..2)
+            .map(|_| (World::with_players(&Level::default(), 3, 2), Vec::new()))
+            .collect();
+        let mut added = [0; 2];
+        for _ in 0..10000 {
+            for (i, session) in sessions.iter_mut().enumerate() {
+                if session.needs_input() {
+                    session.add_local_input(&scripted(i, added[i]));
+                    added[i] += 1;
+                }
+                session.poll().unwrap();
+                let (world, ran) = &mut peers[i];
+                while ran.len() < frames {
+                    match session.next_frame() {
+                        Some(controls) => {
+                            world.step(&controls);
+                            ran.push(controls);
+                        }
+                        None => break,
+                    }
+                }
+            }
+            if peers.iter().all(|(_, ran)| ran.len() == frames) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_micros(200));
+        }
+        peers
+    }
+
+    #[test]
+    fn lockstep_in_sync() {
+        let (a, b) = sessions(3, 0, 0);
+        let mut sessions = [a, b];
+        let peers = run(&mut sessions, 200);
+        let (a, b) = (&peers[0], &peers[1]);
+        assert_eq!(a.1.len(), 200);
+        assert_eq!(a.1, b.1);
+
+        // The input delay shows up as idle frames at the start, after
+        // which each player's input runs in order.
+        assert!(a.1[..3].iter().flatten().all(|c| *c == Control::default()));
+        assert_eq!(a.1[3], vec![scripted(0, 0), scripted(1, 0)]);
+        assert_eq!(a.1[10][1], scripted(1, 7));
+        assert!(a.0.state.hitbox == b.0.state.hitbox);
+        assert!(a.0.state.stats == b.0.state.stats);
+    }
+
+    #[test]
+    fn lockstep_lossy() {
+        // A third of all packets lost and another third out of order
+        // still ends up with the same frames on both peers.
+        let (a, b) = sessions(2, 33, 33);
+        let mut sessions = [a, b];
+        let peers = run(&mut sessions, 300);
+        let (a, b) = (&peers[0], &peers[1]);
+        assert_eq!(a.1.len(), 300);
+        assert_eq!(a.1, b.1);
+        for (frame, controls) in a.1.iter().enumerate().skip(2) {
+            assert_eq!(controls[0], scripted(0, frame - 2));
+            assert_eq!(controls[1], scripted(1, frame - 2));
+        }
+        assert!(a.0.state.hitbox == b.0.state.hitbox);
+        assert!(a.0.state.stats == b.0.state.stats);
+        assert_eq!(sessions[0].frame(), 300);
+    }
+}
@@ -1,343 +1,645 @@
-use sdl2::pixels::Color;
-use sdl2::render::{Canvas, RenderTarget};
-use std::cmp::{max, min};
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use game::control::{process_input, Control};
+use game::bindings::Bindings;
+use game::control::Control;
 use game::error::Error;
-use game::point::Point;
-use game::rect::Rect;
-use game::state::{Entity, EntityId, EntityKind, State};
-
-static ATTACK_FRAMES: &'static [(i32, i32, i32, i32)] = &[
-    (12, -5, 4, 4),    // gap 0
-    (10, -9, 5, 8),    // gap 0
-    (8, -17, 6, 16),   // gap 0
-    (4, -21, 8, 18),   // gap 2
-    (0, -23, 8, 18),   // gap 4
-    (-4, -25, 8, 20),  // gap 4
-    (-8, -23, 8, 18),  // gap 4
-    (-12, -21, 8, 18), // gap 2
-    (-14, -17, 6, 16), // gap 0
-    (-16, -9, 5, 8),   // gap 0
-    (-18, -5, 4, 4),   // gap 0
-];
-
-fn process_scripts(state: &mut State, player_id: EntityId, window: Rect, frame_number: u64) {
-    let player_hitbox = state
-        .entities
-        .get_mut(player_id)
-        .map(|player| player.hitbox);
-    for entity in state.entities.values_mut() {
-        if entity.kind == EntityKind::Monster {
-            let dir = entity.facing_direction;
-            let delta = if entity.agro < 240 {
-                Point::new(
-                    if dir % 2 == 1 { dir - 2 } else { 0 },
-                    if dir % 2 == 0 { dir - 1 } else { 0 },
-                )
-            } else if let Some(hitbox) = player_hitbox {
-                let factor = entity.agro / 320;
-                let target = hitbox.center() - entity.hitbox.center();
-                if target.x.abs() > target.y.abs() {
-                    Point::new(target.x.signum(), 0) * factor
-                } else {
-                    Point::new(0, target.y.signum()) * factor
+use game::event::{Event, Events};
+use game::level::Level;
+use game::lockstep::Lockstep;
+use game::net::UdpTransport;
+use game::render::{render_scene, Color, Renderer};
+use game::replay::Replay;
+use game::rollback::Rollback;
+use game::scene::{Scene, SceneInput};
+use game::sdl_input::{process_input, SdlInput};
+use game::sim::World;
+use game::timestep::Timestep;
+use game::wave::load_waves;
+use game::weapon::load_weapons;
+
+// Logs a frame's events. Scores are as of the world given, which with
+// rollback can be a few frames on from the events.
+fn log_events(world: &World, events: &Events) {
+    // Players are numbered from 1.
+    let number = |id| {
+        world
+            .players
+            .iter()
+            .position(|&p| p == id)
+            .map_or(0, |i| i + 1)
+    };
+    for event in events.iter() {
+        match *event {
+            Event::MonsterKilled { player, .. } | Event::PowerCollected { player, .. } => {
+                if let Some(stats) = world.state.stats.get(player) {
+                    println!(
+                        "player {} score: {} power: {}",
+                        number(player),
+                        stats.score,
+                        stats.power
+                    );
                 }
-            } else {
-                Point::new(0, 0)
-            };
-            let lo = window
-                .grow(-entity.hitbox.size().x)
-                .clamp(entity.hitbox.lo + delta);
-            let hi = lo + entity.hitbox.size();
-            let hitbox = Rect::new(lo, hi);
-
-            // Turn when we hit an object.
-            if hitbox == entity.hitbox {
-                entity.facing_direction = (dir + 1) % 4;
             }
-
-            entity.hitbox = hitbox;
-
-            entity.agro += 1;
+            Event::PlayerDied { player, .. } => {
+                println!("player {} died", number(player));
+                if world.is_over() {
+                    println!("game over");
+                }
+            }
+            Event::WeaponSwitched { weapon, .. } => {
+                if let Some(weapon) = world.state.weapons.get(weapon) {
+                    println!("weapon: {}", weapon.name);
+                }
+            }
+            Event::EntitySpawned { .. } | Event::MonsterHit { .. } => {}
         }
     }
+}
 
-    if frame_number % 100 == 0 {
-        let dir = ((frame_number / 100) % 4) as i32;
-        state.entities.insert(Entity {
-            hitbox: Rect::new_with_size(
-                window.width() as i32 * ((dir + 1) % 2) + 400 * (dir - 2),
-                window.height() as i32 * (dir % 2) + 300 * (dir - 1),
-                32,
-                32,
-            ),
-            kind: EntityKind::Monster,
-            facing_direction: ((frame_number % 17) % 4) as i32,
-            attack_frame: None,
-            attack_box: Rect::default(),
-            agro: 0,
-            score: 0,
-            power: 0,
-        });
+// Whether any player requested something, clearing the request.
+fn take(controls: &mut [Control], input: impl Fn(&mut Control) -> &mut bool) -> bool {
+    let mut requested = false;
+    for control in controls {
+        requested |= std::mem::replace(input(control), false);
     }
+    requested
+}
 
-    if frame_number % 175 == 150 {
-        state.entities.insert(Entity {
-            hitbox: Rect::new_with_size(
-                window.width() as i32 * (frame_number as i32 % 61) / 61,
-                window.height() as i32 * (frame_number as i32 % 67) / 67,
-                32,
-                32,
-            ),
-            kind: EntityKind::Power,
-            facing_direction: 0,
-            attack_frame: None,
-            attack_box: Rect::default(),
-            agro: 0,
-            score: 0,
-            power: 0,
-        });
+// Attack and switch weapons only on initial keypress, so clear them once
+// a frame has taken them.
+fn clear_presses(controls: &mut [Control]) {
+    for control in controls {
+        control.attack_input = false;
+        control.switch_weapon_input = 0;
     }
 }
 
-fn process_action(state: &mut State, player_id: EntityId, control: &Control, window: Rect) {
-    if let Some(player) = state.entities.get_mut(player_id) {
-        let delta = Point::new(control.left_right_input, control.up_down_input) * 2;
-        let lo = window
-            .grow(-player.hitbox.size().x)
-            .clamp(player.hitbox.lo + delta);
-        let hi = lo + player.hitbox.size();
-        player.hitbox = Rect::new(lo, hi);
-
-        player.facing_direction = control.facing_input;
-        player.attack_frame = match player.attack_frame {
-            Some(frame) => {
-                if frame < ATTACK_FRAMES.len() - 1 {
-                    Some(frame + 1)
-                } else {
-                    None
-                }
-            }
-            None => {
-                if control.attack_input {
-                    Some(0)
-                } else {
-                    None
-                }
-            }
-        };
-
-        player.attack_box = match player.attack_frame {
-            Some(frame) => {
-                let b = player.hitbox;
-                let top_center = b.index(0, -1);
-                let attack: Rect = ATTACK_FRAMES[frame].into();
-                let attack = Rect::new(
-                    Point::new(
-                        attack.lo.x,
-                        attack.hi.y - min(attack.height() as i32, 5 * (player.power + 1)),
-                    ),
-                    attack.hi,
-                );
-                (attack + top_center).rotate(b.center(), player.facing_direction * 90)
-            }
-            None => Rect::default(),
-        };
+// Where to record the given game of the session, counting from 1: the
+// path given for the first, and numbered after that, e.g. replay-2.ron.
+fn recording_path(path: &Path, game: u32) -> PathBuf {
+    if game == 1 {
+        return path.to_path_buf();
     }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, game, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, game),
+    };
+    path.with_file_name(name)
 }
 
-fn process_collisions(state: &mut State, player_id: EntityId) {
-    let mut score = 0;
-    let mut power = 0;
-    if let Some(player) = state.entities.get(player_id) {
-        let mut dead = Vec::new();
-
-        for (id, entity) in state.entities.iter() {
-            if entity.kind == EntityKind::Monster {
-                // Monster hit player.
-                if entity.hitbox.has_intersection(player.hitbox)
-                    || entity.attack_box.has_intersection(player.hitbox)
-                {
-                    dead.push(player_id);
-                    break;
-                }
+fn save_recording(path: &Path, recording: &Replay) -> Result<(), Error> {
+    recording.save(path)?;
+    println!(
+        "recorded {} frames to {}",
+        recording.frames.len(),
+        path.display()
+    );
+    Ok(())
+}
 
-                // Player hit moster.
-                if player.attack_box.has_intersection(entity.hitbox) {
-                    dead.push(id);
-                    score += 1;
-                    break;
-                }
-            } else if entity.kind == EntityKind::Power {
-                if player.hitbox.has_intersection(entity.hitbox) {
-                    dead.push(id);
-                    power += 4;
-                    break;
-                }
-            }
-        }
+const QUICKSAVE_PATH: &str = "quicksave.ron";
+
+// Playing against another process over UDP. Each side has one player
+// on the keyboard, and both must start with the same seed and level.
+struct NetOptions {
+    listen: SocketAddr,
+    peer: SocketAddr,
+    player: usize,
+    input_delay: u64,
+    // How many frames to run ahead of the other side's input with
+    // rollback, or None to wait for it in lockstep.
+    rollback: Option<u64>,
+}
 
-        for id in dead {
-            state.entities.remove(id);
+// How long to keep resending our last input on the way out before giving
+// up on the other side acknowledging it, and how long the other side can
+// take to show up, or go quiet after, before we give up on it altogether.
+const QUIT_TIMEOUT: Duration = Duration::from_secs(2);
+const PEER_TIMEOUT: Duration = Duration::from_secs(10);
+
+enum Session {
+    Lockstep(Lockstep<UdpTransport>),
+    Rollback {
+        session: Box<Rollback<UdpTransport>>,
+        // Confirmed frames already recorded, and whether a desync has
+        // been reported yet.
+        handled: u64,
+        desynced: bool,
+    },
+}
+
+impl Session {
+    fn silence(&self) -> Duration {
+        match self {
+            Session::Lockstep(session) => session.silence(),
+            Session::Rollback { session, .. } => session.silence(),
         }
     }
+}
 
-    if let Some(player) = state.entities.get_mut(player_id) {
-        player.score += score;
-        player.power = max(player.power - score + power, 0);
-        if score > 0 || power > 0 {
-            println!("score: {} power: {}", player.score, player.power);
+// Records the rollback frames confirmed since those already handled, up
+// to any quit, returning whether there was one. Frames run on a guess may
+// still change, so only confirmed ones are final enough to record.
+fn record_confirmed(
+    session: &Rollback<UdpTransport>,
+    handled: &mut u64,
+    recording: &mut Option<Replay>,
+) -> bool {
+    while let Some(confirmed) = session.confirmed_controls(*handled) {
+        if confirmed.iter().any(|c| c.quit_input) {
+            return true;
+        }
+        *handled += 1;
+        if let Some(recording) = recording {
+            recording.record(&confirmed);
         }
     }
+    false
 }
 
-// Wrapper for fill_rect since I can't get the type adapters to work properly.
-trait Fill {
-    fn fill(&mut self, r: Rect) -> Result<(), String>;
+struct Options {
+    seed: u64,
+    tick_rate: u32,
+    record: Option<PathBuf>,
+    replay: Option<Replay>,
+    load: Option<PathBuf>,
+    level: Level,
+    players: usize,
+    // One for each player at this computer.
+    bindings: Vec<Bindings>,
+    net: Option<NetOptions>,
 }
 
-impl<T: RenderTarget> Fill for Canvas<T> {
-    fn fill(&mut self, r: Rect) -> Result<(), String> {
-        let r: sdl2::rect::Rect = r.into();
-        self.fill_rect(r)
-    }
+fn next_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, Error> {
+    args.next()
+        .ok_or_else(|| Error::ArgumentError(format!("{} requires a value", flag)))
 }
 
-fn render<T: RenderTarget>(canvas: &mut Canvas<T>, state: &State, player_id: EntityId) -> Result<(), Error> {
-    if let Some(player) = state.entities.get(player_id) {
-        let c = (255*min(player.score, 20)/20) as u8;
-        canvas.set_draw_color(Color::RGB(c, c, c));
-    } else {
-        canvas.set_draw_color(Color::RGB(255, 0, 0));
+fn parse_value<T: FromStr>(value: &str, flag: &str) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::ArgumentError(format!("invalid value for {}: {}", flag, value)))
+}
+
+fn parse_options() -> Result<Options, Error> {
+    let mut seed = None;
+    let mut tick_rate = 60;
+    let mut record = None;
+    let mut replay = None;
+    let mut load = None;
+    let mut level = None;
+    let mut waves = None;
+    let mut weapons = None;
+    let mut players = 1;
+    let mut keys = Vec::new();
+    let mut listen = None;
+    let mut peer = None;
+    let mut net_player = 0;
+    let mut input_delay = 3;
+    let mut rollback = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => {
+                seed = Some(parse_value(&next_value(&mut args, &arg)?, &arg)?);
+            }
+            "--tick-rate" => {
+                tick_rate = parse_value(&next_value(&mut args, &arg)?, &arg)?;
+                if tick_rate == 0 {
+                    return Err(Error::ArgumentError(
+                        "tick rate must be positive".to_string(),
+                    ));
+                }
+            }
+            "--record" => {
+                record = Some(next_value(&mut args, &arg)?.into());
+            }
+            "--replay" => {
+                replay = Some(Replay::load(next_value(&mut args, &arg)?)?);
+            }
+            "--load" => {
+                load = Some(next_value(&mut args, &arg)?.into());
+            }
+            "--level" => {
+                level = Some(Level::load(next_value(&mut args, &arg)?)?);
+            }
+            "--waves" => {
+                waves = Some(load_waves(next_value(&mut args, &arg)?)?);
+            }
+            "--weapons" => {
+                weapons = Some(load_weapons(next_value(&mut args, &arg)?)?);
+            }
+            "--players" => {
+                players = parse_value(&next_value(&mut args, &arg)?, &arg)?;
+                if players == 0 {
+                    return Err(Error::ArgumentError(
+                        "there must be at least one player".to_string(),
+                    ));
+                }
+            }
+            // Once for each player, in order.
+            "--keys" => {
+                keys.push(next_value(&mut args, &arg)?);
+            }
+            "--listen" => {
+                listen = Some(parse_value(&next_value(&mut args, &arg)?, &arg)?);
+            }
+            "--peer" => {
+                peer = Some(parse_value(&next_value(&mut args, &arg)?, &arg)?);
+            }
+            // Which of the two networked players this is, 1 or 2.
+            "--player" => {
+                net_player = parse_value::<usize>(&next_value(&mut args, &arg)?, &arg)?;
+                if net_player != 1 && net_player != 2 {
+                    return Err(Error::ArgumentError("player must be 1 or 2".to_string()));
+                }
+                net_player -= 1;
+            }
+            "--input-delay" => {
+                input_delay = parse_value(&next_value(&mut args, &arg)?, &arg)?;
+            }
+            "--rollback" => {
+                let frames = parse_value(&next_value(&mut args, &arg)?, &arg)?;
+                if frames == 0 {
+                    return Err(Error::ArgumentError(
+                        "rollback must allow at least one frame".to_string(),
+                    ));
+                }
+                rollback = Some(frames);
+            }
+            _ => return Err(Error::ArgumentError(format!("unknown argument: {}", arg))),
+        }
     }
-    canvas.clear();
 
-    for entity in state.entities.values() {
-        match entity.kind {
-            EntityKind::Player => canvas.set_draw_color(Color::RGB(
-                (255 * (8 - min(entity.power, 8)) / 8) as u8,
-                (255 * (8 - min(entity.power, 8)) / 8) as u8,
-                255,
-            )),
-            EntityKind::Monster => canvas.set_draw_color(Color::RGB(
-                255,
-                (255 * (3 - min(entity.agro / 320, 3)) / 3) as u8,
-                (255 * (3 - min(entity.agro / 320, 3)) / 3) as u8,
-            )),
-            EntityKind::Power => canvas.set_draw_color(Color::RGB(0, 0, 255)),
+    let net = match (listen, peer) {
+        (Some(listen), Some(peer)) => Some(NetOptions {
+            listen,
+            peer,
+            player: net_player,
+            input_delay,
+            rollback,
+        }),
+        (None, None) => None,
+        _ => {
+            return Err(Error::ArgumentError(
+                "--listen and --peer must be given together".to_string(),
+            ))
         }
-        canvas.fill(entity.hitbox)?;
-        if entity.kind == EntityKind::Player {
-            canvas.set_draw_color(Color::RGB(0, 255, 0));
-            let b = entity.hitbox;
-            let w = 4;
-            let face: Rect = (b.index(-1, -1), b.index(1, -1) + (0, w)).into();
-            let face = face.rotate(b.center(), entity.facing_direction * 90);
-            canvas.fill(face)?;
-
-            canvas.set_draw_color(Color::RGB(255, 255, 0));
-            canvas.fill(entity.attack_box)?;
+    };
+    // Replays start from the level and seed, not from a saved game.
+    if load.is_some() && (record.is_some() || replay.is_some()) {
+        return Err(Error::ArgumentError(
+            "--load can't be combined with --record or --replay".to_string(),
+        ));
+    }
+    if net.is_some() {
+        if replay.is_some() || load.is_some() || players != 1 {
+            return Err(Error::ArgumentError(
+                "networked play can't be combined with --replay, --load or --players".to_string(),
+            ));
         }
+        if seed.is_none() {
+            return Err(Error::ArgumentError(
+                "networked play needs the same --seed on both sides".to_string(),
+            ));
+        }
+        players = 2;
     }
 
-    Ok(())
+    // A replay must run with the level and seed it was recorded with.
+    // Otherwise default to a seed from the clock so each session is
+    // different.
+    let level = match (&replay, level) {
+        (Some(replay), _) => replay.level.clone(),
+        (None, Some(level)) => level,
+        (None, None) => Level::default(),
+    };
+    let level = match &replay {
+        Some(_) => level,
+        None => {
+            let level = Level {
+                waves: waves.unwrap_or(level.waves),
+                weapons: weapons.unwrap_or(level.weapons),
+                ..level
+            };
+            level.validate()?;
+            level
+        }
+    };
+    // As does its number of players.
+    let players = match &replay {
+        Some(replay) => replay.players,
+        None => players,
+    };
+    let local_players = if net.is_some() { 1 } else { players };
+    if keys.len() > local_players {
+        return Err(Error::ArgumentError(format!(
+            "--keys given {} times for {} players",
+            keys.len(),
+            local_players
+        )));
+    }
+    let bindings = (0..local_players)
+        .map(|player| match keys.get(player) {
+            Some(path) => Bindings::load(path, player),
+            None => Ok(Bindings::for_player(player)),
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let seed = match (&replay, seed) {
+        (Some(replay), _) => replay.seed,
+        (None, Some(seed)) => seed,
+        (None, None) => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|t| t.as_nanos() as u64)
+            .unwrap_or(0),
+    };
+
+    Ok(Options {
+        seed,
+        tick_rate,
+        record,
+        replay,
+        load,
+        level,
+        players,
+        bindings,
+        net,
+    })
 }
 
 fn main() -> Result<(), Error> {
+    let options = parse_options()?;
+    println!("seed: {}", options.seed);
+
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
 
+    let mut world = match &options.load {
+        Some(path) => World::load(path)?,
+        None => World::with_players(&options.level, options.seed, options.players),
+    };
+
     let window = video_subsystem
-        .window("demo", 800, 600)
+        .window("demo", world.arena.width(), world.arena.height())
         .position_centered()
         .build()?;
 
-    let mut canvas = window.into_canvas().build()?;
-
-    let mut state = State::default();
-    let player_id = state.entities.insert(Entity {
-        hitbox: Rect::new_with_size(400, 300, 32, 32),
-        kind: EntityKind::Player,
-        facing_direction: 0,
-        attack_frame: None,
-        attack_box: Rect::default(),
-        agro: 0,
-        score: 0,
-        power: 0,
-    });
-
-    // Add monsters.
-    state.entities.insert(Entity {
-        hitbox: Rect::new_with_size(300, 200, 32, 32),
-        kind: EntityKind::Monster,
-        facing_direction: 0,
-        attack_frame: None,
-        attack_box: Rect::default(),
-        agro: 0,
-        score: 0,
-        power: 0,
-    });
-    state.entities.insert(Entity {
-        hitbox: Rect::new_with_size(500, 200, 32, 32),
-        kind: EntityKind::Monster,
-        facing_direction: 1,
-        attack_frame: None,
-        attack_box: Rect::default(),
-        agro: 0,
-        score: 0,
-        power: 0,
-    });
-    state.entities.insert(Entity {
-        hitbox: Rect::new_with_size(300, 400, 32, 32),
-        kind: EntityKind::Monster,
-        facing_direction: 2,
-        attack_frame: None,
-        attack_box: Rect::default(),
-        agro: 0,
-        score: 0,
-        power: 0,
-    });
-    state.entities.insert(Entity {
-        hitbox: Rect::new_with_size(500, 400, 32, 32),
-        kind: EntityKind::Monster,
-        facing_direction: 3,
-        attack_frame: None,
-        attack_box: Rect::default(),
-        agro: 0,
-        score: 0,
-        power: 0,
-    });
-
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    let mut canvas = window.into_canvas().present_vsync().build()?;
+
+    canvas.set_color(Color::rgb(0, 0, 0));
     canvas.clear();
     canvas.present();
     let mut event_pump = sdl_context.event_pump()?;
-    let mut control = Control::default();
-    let mut frame_number: u64 = 0;
-    loop {
-        process_input(&mut event_pump, &mut control)?;
-        if control.quit_input {
+    let bindings = options.bindings;
+    let mut input = SdlInput::new(sdl_context.game_controller()?, &bindings)?;
+    let mut controls = vec![Control::default(); bindings.len()];
+    let mut session = match &options.net {
+        Some(net) => {
+            let transport = UdpTransport::bind(net.listen, net.peer)?;
+            let peer = 1 - net.player;
+            let peers = vec![(peer, transport)];
+            println!("waiting for player {} at {}", peer + 1, net.peer);
+            Some(match net.rollback {
+                Some(frames) => Session::Rollback {
+                    session: Box::new(Rollback::new(net.player, 2, net.input_delay, frames, peers)),
+                    handled: 0,
+                    desynced: false,
+                },
+                None => Session::Lockstep(Lockstep::new(net.player, 2, net.input_delay, peers)),
+            })
+        }
+        None => None,
+    };
+    let mut timestep = Timestep::new(options.tick_rate);
+    let mut last_frame = Instant::now();
+
+    // When playing back a replay, the recorded controls replace the
+    // keyboard (which is still polled so the window can be closed).
+    let (mut seed, level, players) = (options.seed, options.level, options.players);
+    let mut game = 1;
+    let mut playback = options.replay.map(|replay| replay.frames.into_iter());
+    let mut recording = options
+        .record
+        .as_ref()
+        .map(|_| Replay::new(seed, level.clone(), players));
+
+    // Replays, networked play and loaded games go straight into the game.
+    let mut scene = if playback.is_some() || session.is_some() || options.load.is_some() {
+        Scene::Playing
+    } else {
+        Scene::Title
+    };
+
+    'main: loop {
+        process_input(&mut event_pump, &mut input, &mut controls, &bindings)?;
+
+        // Any player can quit, pause, save or load for everyone. Over the
+        // network only quitting reaches the other side, so it can stop
+        // too, and pausing and loading aren't allowed, since the other
+        // side would be left waiting or out of step.
+        if controls.iter().any(|c| c.quit_input) {
+            match &mut session {
+                Some(Session::Lockstep(session)) => session.add_local_input(&controls[0]),
+                Some(Session::Rollback { session, .. }) => {
+                    // Too far ahead of the other side, the quit has to
+                    // wait for a frame to run like any other input.
+                    let start = Instant::now();
+                    while !session.advance(&mut world, &controls[0])?
+                        && start.elapsed() < QUIT_TIMEOUT
+                    {
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                }
+                None => {}
+            }
+            // Over the network, the recording only has frames that ran
+            // with every player's input, and just ends at the last one.
+            if let (None, Some(recording)) = (&session, &mut recording) {
+                recording.record(&controls);
+            }
+            break;
+        }
+        if let Some(Session::Lockstep(session)) = &mut session {
+            session.poll()?;
+        }
+        if session.as_ref().is_some_and(|s| s.silence() > PEER_TIMEOUT) {
+            println!("lost contact with the other player");
             break;
         }
 
-        let size: Point = canvas.window().size().into();
-        let rect = ((0, 0), size).into();
+        // A failed quicksave or quickload shouldn't end the game.
+        if take(&mut controls, |c| &mut c.save_input) {
+            match world.save(QUICKSAVE_PATH) {
+                Ok(()) => println!("saved to {}", QUICKSAVE_PATH),
+                Err(error) => println!("failed to save: {:?}", error),
+            }
+        }
+        if take(&mut controls, |c| &mut c.load_input) {
+            match World::load(QUICKSAVE_PATH) {
+                Ok(_) if session.is_some() => println!("can't load during networked play"),
+                Ok(_) if recording.is_some() || playback.is_some() => {
+                    println!("can't load while recording or playing back")
+                }
+                Ok(loaded) => world = loaded,
+                Err(error) => println!("failed to load: {:?}", error),
+            }
+        }
 
-        // Note: start by processing collisions first. This is in effect
-        // capturing the collisions from the *last frame*, which is important
-        // so that you can actually see something you hit instead of
-        // dissappearing off the screen before you even see it.
-        process_collisions(&mut state, player_id);
-        process_scripts(&mut state, player_id, rect, frame_number);
-        process_action(&mut state, player_id, &control, rect);
+        // Attacking starts a game from the title or game over screen,
+        // without also attacking in it.
+        let mut input = SceneInput {
+            start: controls.iter().any(|c| c.attack_input),
+            pause: take(&mut controls, |c| &mut c.pause_input),
+        };
+        if input.pause && session.is_some() {
+            println!("can't pause during networked play");
+            input.pause = false;
+        }
+        let mut next = scene.next(&world, input);
+        if !scene.is_running() {
+            clear_presses(&mut controls);
+        }
+        if scene == Scene::GameOver && next == Scene::Playing && world.is_over() {
+            // A new game can't be kept in step with the other side or
+            // the replay, so those stay over.
+            if session.is_some() || playback.is_some() {
+                println!("can't restart during networked play or playback");
+                next = Scene::GameOver;
+            } else if let Some(level) = world.level.clone() {
+                let players = world.players.len();
+                seed += 1;
+                println!("seed: {}", seed);
+                world = World::with_players(&level, seed, players);
+
+                // Each game is recorded to its own file.
+                if let (Some(path), Some(recording)) = (&options.record, &mut recording) {
+                    save_recording(&recording_path(path, game), recording)?;
+                    *recording = Replay::new(seed, level, players);
+                }
+                game += 1;
+            } else {
+                println!("can't restart a game saved without its level");
+                next = Scene::GameOver;
+            }
+        }
+        match (scene, next) {
+            (Scene::Playing, Scene::Paused) => println!("paused"),
+            (Scene::Paused, Scene::Playing) => println!("resumed"),
+            _ => {}
+        }
+        scene = next;
+
+        // Step the simulation at a fixed rate, independent of how long
+        // rendering and presenting take. Time spent outside the game
+        // doesn't count, except over the network, where the other side
+        // is still waiting on our input once the game is over.
+        let now = Instant::now();
+        let running = scene.is_running() || (session.is_some() && scene == Scene::GameOver);
+        let ticks = if running {
+            timestep.advance(now - last_frame)
+        } else {
+            0
+        };
+        for _ in 0..ticks {
+            // Networked frames wait until the other side's input is in,
+            // or for rollback until they're not too far ahead of it, and
+            // the local input is only taken when there's room for it.
+            let frame_controls = match (&mut playback, &mut session) {
+                (Some(frames), _) => frames.next().unwrap_or_default(),
+                (
+                    None,
+                    Some(Session::Rollback {
+                        session,
+                        handled,
+                        desynced,
+                    }),
+                ) => {
+                    if !session.advance(&mut world, &controls[0])? {
+                        break;
+                    }
+                    clear_presses(&mut controls);
+                    for events in session.take_events() {
+                        log_events(&world, &events);
+                    }
+                    if let (Some(frame), false) = (session.desync(), *desynced) {
+                        println!("out of sync with the other player since frame {}", frame);
+                        *desynced = true;
+                    }
+
+                    if record_confirmed(session, handled, &mut recording) {
+                        break 'main;
+                    }
+                    continue;
+                }
+                (None, Some(Session::Lockstep(session))) => {
+                    if session.needs_input() {
+                        session.add_local_input(&controls[0]);
+                        clear_presses(&mut controls);
+                        session.poll()?;
+                    }
+                    match session.next_frame() {
+                        Some(frame_controls) => frame_controls,
+                        None => break,
+                    }
+                }
+                (None, None) => {
+                    let frame_controls = controls.clone();
+                    clear_presses(&mut controls);
+                    frame_controls
+                }
+            };
+            if frame_controls.is_empty() || frame_controls.iter().any(|c| c.quit_input) {
+                break 'main;
+            }
 
-        render(&mut canvas, &state, player_id)?;
+            world.step(&frame_controls);
+            log_events(&world, &world.state.events);
+            if let Some(recording) = &mut recording {
+                recording.record(&frame_controls);
+            }
+        }
+        last_frame = now;
+
+        render_scene(&mut canvas, scene, &world, timestep.alpha())?;
 
         canvas.present();
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
-        frame_number += 1;
+    }
+
+    // The other side can't go on without our input, so make sure it has
+    // all of it, quit included, before leaving. With rollback, also wait
+    // for the frames we ran to be confirmed, so the recording has them,
+    // unless it's already got as far as a quit.
+    if let Some(session) = &mut session {
+        let start = Instant::now();
+        while start.elapsed() < QUIT_TIMEOUT {
+            let done = match session {
+                Session::Lockstep(session) => {
+                    session.poll()?;
+                    session.is_acked()
+                }
+                Session::Rollback {
+                    session, handled, ..
+                } => {
+                    session.poll(&mut world)?;
+                    let quit = record_confirmed(session, handled, &mut recording);
+                    session.is_acked() && (quit || *handled == session.frame())
+                }
+            };
+            if done {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    if let (Some(path), Some(recording)) = (&options.record, &recording) {
+        save_recording(&recording_path(path, game), recording)?;
     }
     Ok(())
 }
@@ -0,0 +1,479 @@
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::control::Control;
+use crate::error::Error;
+use crate::rng::Rng;
+
+// Most frames of input sent in one packet. A peer that falls further
+// behind than this catches up over several packets.
+const MAX_FRAMES_PER_PACKET: usize = 32;
+
+// Largest packet we expect to receive. Packets are bounded by the number
+// of frames of input they carry, so this leaves plenty of room.
+const MAX_PACKET_SIZE: usize = 65536;
+
+// Sends and receives whole packets to and from one other peer. Delivery
+// is unreliable: packets may be lost, duplicated or arrive out of order.
+pub trait Transport {
+    fn send(&mut self, packet: &[u8]) -> Result<(), Error>;
+    // Doesn't block. None once there's nothing left to receive.
+    fn recv(&mut self) -> Result<Option<Vec<u8>>, Error>;
+}
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+}
+
+impl UdpTransport {
+    // Only packets from the peer are received.
+    pub fn new(socket: UdpSocket, peer: SocketAddr) -> Result<UdpTransport, Error> {
+        socket.connect(peer)?;
+        socket.set_nonblocking(true)?;
+        Ok(UdpTransport { socket })
+    }
+
+    pub fn bind(local: SocketAddr, peer: SocketAddr) -> Result<UdpTransport, Error> {
+        UdpTransport::new(UdpSocket::bind(local)?, peer)
+    }
+}
+
+// Until the peer is listening, sending to it may fail with the port
+// reported unreachable. That's no different from the packet being lost.
+fn is_transient(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::WouldBlock | ErrorKind::ConnectionRefused | ErrorKind::Interrupted
+    )
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, packet: &[u8]) -> Result<(), Error> {
+        match self.socket.send(packet) {
+            Err(error) if !is_transient(&error) => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn recv(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let mut buffer = vec![0; MAX_PACKET_SIZE];
+        loop {
+            match self.socket.recv(&mut buffer) {
+                Ok(size) => {
+                    buffer.truncate(size);
+                    return Ok(Some(buffer));
+                }
+                Err(ref error) if error.kind() == ErrorKind::ConnectionRefused => continue,
+                Err(ref error) if is_transient(error) => return Ok(None),
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+}
+
+// Simulates a bad network for testing, on the sending side: drops the
+// given percentage of packets, and holds back another percentage to
+// send after the next one. Driven by its own seeded RNG, so a test sees
+// the same losses every run.
+pub struct LossyTransport<T> {
+    inner: T,
+    rng: Rng,
+    loss: i32,
+    reorder: i32,
+    held: Vec<Vec<u8>>,
+}
+
+impl<T: Transport> LossyTransport<T> {
+    pub fn new(inner: T, seed: u64, loss: i32, reorder: i32) -> LossyTransport<T> {
+        LossyTransport {
+            inner,
+            rng: Rng::new(seed),
+            loss,
+            reorder,
+            held: Vec::new(),
+        }
+    }
+}
+
+impl<T: Transport> Transport for LossyTransport<T> {
+    fn send(&mut self, packet: &[u8]) -> Result<(), Error> {
+        if self.rng.range(0, 100) < self.loss {
+            return Ok(());
+        }
+        if self.rng.range(0, 100) < self.reorder {
+            self.held.push(packet.to_vec());
+            return Ok(());
+        }
+        self.inner.send(packet)?;
+        for held in std::mem::take(&mut self.held) {
+            self.inner.send(&held)?;
+        }
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        self.inner.recv()
+    }
+}
+
+type Queue = Rc<RefCell<VecDeque<(u64, Vec<u8>)>>>;
+
+// Connects peers within one process, for tests. Time only passes when
+// the network ticks, so latency is exact and tests don't have to sleep.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    clock: Rc<Cell<u64>>,
+}
+
+impl MemoryNetwork {
+    pub fn tick(&self) {
+        self.clock.set(self.clock.get() + 1);
+    }
+
+    // Two ends of a link on which packets take the given number of ticks
+    // to arrive. They always arrive, and in order.
+    pub fn link(&self, latency: u64) -> (MemoryTransport, MemoryTransport) {
+        let (a, b) = (Queue::default(), Queue::default());
+        let end = |inbox: &Queue, outbox: &Queue| MemoryTransport {
+            clock: self.clock.clone(),
+            latency,
+            inbox: inbox.clone(),
+            outbox: outbox.clone(),
+        };
+        (end(&a, &b), end(&b, &a))
+    }
+}
+
+pub struct MemoryTransport {
+    clock: Rc<Cell<u64>>,
+    latency: u64,
+    inbox: Queue,
+    outbox: Queue,
+}
+
+impl Transport for MemoryTransport {
+    fn send(&mut self, packet: &[u8]) -> Result<(), Error> {
+        let arrival = self.clock.get() + self.latency;
+        self.outbox
+            .borrow_mut()
+            .push_back((arrival, packet.to_vec()));
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let mut inbox = self.inbox.borrow_mut();
+        match inbox.front() {
+            Some(&(arrival, _)) if arrival <= self.clock.get() => {
+                Ok(inbox.pop_front().map(|(_, packet)| packet))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+// What peers send each other. Each packet repeats every input the
+// receiver hasn't acknowledged yet, so a lost packet is made up for by
+// the next one that gets through.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Packet {
+    // The sender's player index, and its controls for consecutive frames
+    // starting at the given one.
+    pub player: usize,
+    pub start: u64,
+    pub controls: Vec<Control>,
+    // How many frames of the receiver's controls the sender has, from
+    // the start of the game.
+    pub ack: u64,
+    // A frame the sender has confirmed and its checksum of the world
+    // after it, for spotting peers that have drifted apart.
+    #[serde(default)]
+    pub checksum: Option<(u64, u64)>,
+}
+
+impl Packet {
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        Ok(ron::to_string(self)?.into_bytes())
+    }
+
+    // Anything that isn't a valid packet is garbage from the network,
+    // and ignored.
+    pub fn decode(bytes: &[u8]) -> Option<Packet> {
+        ron::from_str(std::str::from_utf8(bytes).ok()?).ok()
+    }
+}
+
+struct Peer<T> {
+    transport: T,
+    player: usize,
+    // How many frames of our input the peer has acknowledged.
+    acked: u64,
+    // When we last heard from the peer, if we have yet.
+    heard: Option<Instant>,
+}
+
+// Every player's controls, kept in step with the other peers: the local
+// player's are sent to all of them, and theirs are taken in as they
+// arrive. Each player's controls run from the first frame up to the
+// last one that's arrived.
+//
+// Local input is scheduled the given number of frames ahead, which
+// gives it that long to reach the other peers before it's needed. The
+// frames before then have no input from anyone.
+pub struct InputSync<T> {
+    local: usize,
+    peers: Vec<Peer<T>>,
+    inputs: Vec<Vec<Control>>,
+    created: Instant,
+}
+
+impl<T: Transport> InputSync<T> {
+    // Takes a transport to each other peer, with the player it's for.
+    pub fn new(local: usize, players: usize, delay: u64, peers: Vec<(usize, T)>) -> InputSync<T> {
+        assert!(local < players && peers.iter().all(|&(p, _)| p < players && p != local));
+        InputSync {
+            local,
+            peers: peers
+                .into_iter()
+                .map(|(player, transport)| Peer {
+                    transport,
+                    player,
+                    acked: 0,
+                    heard: None,
+                })
+                .collect(),
+            inputs: vec![vec![Control::default(); delay as usize]; players],
+            created: Instant::now(),
+        }
+    }
+
+    pub fn local_player(&self) -> usize {
+        self.local
+    }
+
+    pub fn players(&self) -> usize {
+        self.inputs.len()
+    }
+
+    // How many frames of the player's controls we have.
+    pub fn len(&self, player: usize) -> u64 {
+        self.inputs[player].len() as u64
+    }
+
+    pub fn get(&self, player: usize, frame: u64) -> Option<&Control> {
+        self.inputs[player].get(frame as usize)
+    }
+
+    // The controls of every player for a frame, if they've all arrived.
+    pub fn frame(&self, frame: u64) -> Option<Vec<Control>> {
+        (0..self.players())
+            .map(|player| self.get(player, frame).cloned())
+            .collect()
+    }
+
+    // The latest controls we have from a player.
+    pub fn last(&self, player: usize) -> Option<&Control> {
+        self.inputs[player].last()
+    }
+
+    pub fn add_local_input(&mut self, control: &Control) {
+        self.inputs[self.local].push(control.clone());
+    }
+
+    // Whether every peer has all of our input.
+    pub fn is_acked(&self) -> bool {
+        let sent = self.inputs[self.local].len() as u64;
+        self.peers.iter().all(|peer| peer.acked >= sent)
+    }
+
+    // How long it's been since the quietest peer last sent anything, or
+    // since we started, for a peer that never has.
+    pub fn silence(&self) -> Duration {
+        self.peers
+            .iter()
+            .map(|peer| peer.heard.unwrap_or(self.created).elapsed())
+            .max()
+            .unwrap_or_default()
+    }
+
+    // Sends every peer the local input it hasn't acknowledged, along with
+    // the given checksum.
+    pub fn send(&mut self, checksum: Option<(u64, u64)>) -> Result<(), Error> {
+        let sent = &self.inputs[self.local];
+        for peer in &mut self.peers {
+            let start = (peer.acked as usize).min(sent.len());
+            let end = (start + MAX_FRAMES_PER_PACKET).min(sent.len());
+            let packet = Packet {
+                player: self.local,
+                start: start as u64,
+                controls: sent[start..end].to_vec(),
+                ack: self.inputs[peer.player].len() as u64,
+                checksum,
+            };
+            peer.transport.send(&packet.encode()?)?;
+        }
+        Ok(())
+    }
+
+    // Takes in everything the peers have sent, returning the checksums
+    // that came with it.
+    pub fn receive(&mut self) -> Result<Vec<(u64, u64)>, Error> {
+        let mut checksums = Vec::new();
+        for peer in &mut self.peers {
+            while let Some(bytes) = peer.transport.recv()? {
+                let packet = match Packet::decode(&bytes) {
+                    Some(packet) if packet.player == peer.player => packet,
+                    _ => continue,
+                };
+                peer.acked = peer.acked.max(packet.ack);
+                peer.heard = Some(Instant::now());
+                checksums.extend(packet.checksum);
+
+                // Packets can arrive late or twice, so only take the
+                // frames following the ones we already have.
+                let inputs = &mut self.inputs[peer.player];
+                for (frame, control) in (packet.start..).zip(packet.controls) {
+                    if frame == inputs.len() as u64 {
+                        inputs.push(control);
+                    }
+                }
+            }
+        }
+        Ok(checksums)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loopback() -> (UdpTransport, UdpTransport) {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (a_addr, b_addr) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+        (
+            UdpTransport::new(a, b_addr).unwrap(),
+            UdpTransport::new(b, a_addr).unwrap(),
+        )
+    }
+
+    // Loopback delivery is quick, but not immediate. Receives up to and
+    // including the given last packet.
+    fn recv_until(transport: &mut impl Transport, last: &[u8]) -> Vec<Vec<u8>> {
+        let mut received = Vec::new();
+        for _ in 0..1000 {
+            while let Some(packet) = transport.recv().unwrap() {
+                received.push(packet);
+            }
+            if received.last().is_some_and(|p| p == last) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        received
+    }
+
+    #[test]
+    fn net_udp() {
+        let (mut a, mut b) = loopback();
+        assert_eq!(b.recv().unwrap(), None);
+
+        let packet = Packet {
+            player: 1,
+            start: 3,
+            controls: vec![Control::default(); 2],
+            ack: 5,
+            checksum: Some((4, 1234)),
+        };
+        let bytes = packet.encode().unwrap();
+        a.send(&bytes).unwrap();
+        let received = recv_until(&mut b, &bytes);
+        assert_eq!(received.len(), 1);
+        assert_eq!(Packet::decode(&received[0]), Some(packet));
+        assert_eq!(Packet::decode(b"garbage"), None);
+    }
+
+    #[test]
+    fn net_lossy() {
+        let (a, mut b) = loopback();
+        let mut a = LossyTransport::new(a, 0, 25, 25);
+        for i in 0..100u8 {
+            a.send(&[i]).unwrap();
+        }
+        a.inner.send(&[255]).unwrap();
+
+        // Some are lost and some arrive late, but none are made up.
+        let mut received: Vec<u8> = recv_until(&mut b, &[255]).iter().map(|p| p[0]).collect();
+        assert_eq!(received.pop(), Some(255));
+        assert!(received.len() > 50 && received.len() < 100);
+        assert!(received.windows(2).any(|w| w[0] > w[1]));
+        let mut sorted = received.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), received.len());
+    }
+
+    #[test]
+    fn net_memory() {
+        let network = MemoryNetwork::default();
+        let (mut a, mut b) = network.link(2);
+        a.send(&[1]).unwrap();
+        network.tick();
+        a.send(&[2]).unwrap();
+        b.send(&[3]).unwrap();
+        assert_eq!(b.recv().unwrap(), None);
+        network.tick();
+        assert_eq!(b.recv().unwrap(), Some(vec![1]));
+        assert_eq!(b.recv().unwrap(), None);
+        network.tick();
+        assert_eq!(b.recv().unwrap(), Some(vec![2]));
+        assert_eq!(a.recv().unwrap(), Some(vec![3]));
+    }
+
+    #[test]
+    fn net_input_sync() {
+        let network = MemoryNetwork::default();
+        let (a, b) = network.link(0);
+        let mut a = InputSync::new(0, 2, 1, vec![(1, LossyTransport::new(a, 0, 50, 0))]);
+        let mut b = InputSync::new(1, 2, 1, vec![(0, b)]);
+        assert_eq!(a.frame(0), Some(vec![Control::default(); 2]));
+        assert_eq!(a.frame(1), None);
+
+        // Everything gets through in the end, in order, despite losing
+        // half of what's sent.
+        let mut control = Control::default();
+        for frame in 0..20 {
+            control.left_right_input = frame % 3 - 1;
+            a.add_local_input(&control);
+            a.send(Some((frame as u64, 7))).unwrap();
+            b.send(None).unwrap();
+            a.receive().unwrap();
+            b.receive().unwrap();
+        }
+        for _ in 0..10 {
+            a.send(None).unwrap();
+            b.send(None).unwrap();
+            a.receive().unwrap();
+            b.receive().unwrap();
+        }
+        assert_eq!(b.len(0), 21);
+        assert_eq!(b.get(0, 5).unwrap().left_right_input, 0);
+        assert_eq!(b.get(0, 21), None);
+        assert_eq!(b.last(0).unwrap().left_right_input, 0);
+        assert_eq!(a.len(1), 1);
+        assert!(a.is_acked() && b.is_acked());
+        a.add_local_input(&control);
+        assert!(!a.is_acked());
+        assert!(a.silence() < Duration::from_secs(10));
+
+        // A peer that never shows up goes quiet from the start.
+        let (c, _) = network.link(0);
+        let c = InputSync::new(0, 2, 1, vec![(1, c)]);
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(c.silence() >= Duration::from_millis(20));
+    }
+}
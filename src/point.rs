@@ -1,27 +1,81 @@
+use serde::{Deserialize, Serialize};
 use std::ops::{Add, Div, Mul, Sub};
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub struct Point {
-    pub x: i32,
-    pub y: i32,
+use crate::fixed::Fixed;
+
+// Whole pixels by default. Point<Fixed> is used for sub-pixel positions
+// and velocities.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Point<T = i32> {
+    pub x: T,
+    pub y: T,
 }
 
-impl Point {
-    pub fn new(x: i32, y: i32) -> Point {
+impl<T> Point<T> {
+    pub fn new(x: T, y: T) -> Point<T> {
         Point { x, y }
     }
+}
 
+impl Point {
     pub fn rotate(self, origin: Point, angle: i32 /* degrees CCW */) -> Point {
         let delta = self - origin;
-        let rotated = match angle {
+        let rotated = match angle.rem_euclid(360) {
             0 => delta,
             90 => Point::new(delta.y, -delta.x),
             180 => Point::new(-delta.x, -delta.y),
             270 => Point::new(-delta.y, delta.x),
-            _ => unimplemented!(),
+            // Facings are 45 degrees apart, and the simulation turns
+            // things to face them, so these need to come out the same on
+            // every machine: no floating point.
+            angle if angle % 45 == 0 => {
+                let x = div_sqrt2(delta.x + delta.y);
+                let y = div_sqrt2(delta.y - delta.x);
+                return Point::new(x, y).rotate(Point::new(0, 0), angle - 45) + origin;
+            }
+            angle => {
+                // Same as above: y points down, so counterclockwise on
+                // screen is clockwise in the usual math convention.
+                let (sin, cos) = (angle as f64).to_radians().sin_cos();
+                let (x, y) = (delta.x as f64, delta.y as f64);
+                Point::new(
+                    (x * cos + y * sin).round() as i32,
+                    (y * cos - x * sin).round() as i32,
+                )
+            }
         };
         rotated + origin
     }
+
+    // Linear interpolation between self (alpha = 0) and other (alpha = 1).
+    pub fn lerp(self, other: Point, alpha: f32) -> Point {
+        let delta = other - self;
+        self + Point::new(
+            (delta.x as f32 * alpha).round() as i32,
+            (delta.y as f32 * alpha).round() as i32,
+        )
+    }
+}
+
+// The closest integer to v / sqrt(2). That's never halfway between two
+// integers, so it's floor(|v| * sqrt(2)) / 2, rounded up.
+fn div_sqrt2(v: i32) -> i32 {
+    let v = v as i64;
+    let n = ((2 * v * v) as u64).isqrt().div_ceil(2);
+    v.signum() as i32 * n as i32
+}
+
+impl Point<Fixed> {
+    // The pixel this falls in.
+    pub fn floor(self) -> Point {
+        Point::new(self.x.floor(), self.y.floor())
+    }
+}
+
+impl From<Point> for Point<Fixed> {
+    fn from(p: Point) -> Point<Fixed> {
+        Point::new(p.x.into(), p.y.into())
+    }
 }
 
 impl From<(i32, i32)> for Point {
@@ -39,10 +93,10 @@ impl From<(u32, u32)> for Point {
     }
 }
 
-impl Add for Point {
-    type Output = Point;
+impl<T: Add<Output = T>> Add for Point<T> {
+    type Output = Point<T>;
 
-    fn add(self, other: Point) -> Point {
+    fn add(self, other: Point<T>) -> Point<T> {
         Point {
             x: self.x + other.x,
             y: self.y + other.y,
@@ -67,10 +121,10 @@ impl Add<(i32, i32)> for Point {
     }
 }
 
-impl Sub for Point {
-    type Output = Point;
+impl<T: Sub<Output = T>> Sub for Point<T> {
+    type Output = Point<T>;
 
-    fn sub(self, other: Point) -> Point {
+    fn sub(self, other: Point<T>) -> Point<T> {
         Point {
             x: self.x - other.x,
             y: self.y - other.y,
@@ -95,10 +149,10 @@ impl Sub<(i32, i32)> for Point {
     }
 }
 
-impl Mul<i32> for Point {
-    type Output = Point;
+impl<T: Mul<Output = T> + Copy> Mul<T> for Point<T> {
+    type Output = Point<T>;
 
-    fn mul(self, other: i32) -> Point {
+    fn mul(self, other: T) -> Point<T> {
         Point {
             x: self.x * other,
             y: self.y * other,
@@ -106,10 +160,10 @@ impl Mul<i32> for Point {
     }
 }
 
-impl Div<i32> for Point {
-    type Output = Point;
+impl<T: Div<Output = T> + Copy> Div<T> for Point<T> {
+    type Output = Point<T>;
 
-    fn div(self, other: i32) -> Point {
+    fn div(self, other: T) -> Point<T> {
         Point {
             x: self.x / other,
             y: self.y / other,
@@ -151,6 +205,27 @@ mod tests {
         assert_eq!(Point::new(4, 6) / 2, Point::new(2, 3));
     }
 
+    #[test]
+    fn point_fixed() {
+        let half = Fixed::from_ratio(1, 2);
+        let p: Point<Fixed> = Point::new(1, -1).into();
+        let q = p + Point::new(half, half) * Fixed::from(3);
+        assert_eq!(q, Point::new(Fixed::from_ratio(5, 2), half));
+        assert_eq!(q.floor(), Point::new(2, 0));
+        assert_eq!((q - p).floor(), Point::new(1, 1));
+        assert_eq!((q / Fixed::from(2)).floor(), Point::new(1, 0));
+    }
+
+    #[test]
+    fn point_lerp() {
+        let p1 = Point::new(0, 10);
+        let p2 = Point::new(4, -10);
+        assert_eq!(p1.lerp(p2, 0.0), p1);
+        assert_eq!(p1.lerp(p2, 1.0), p2);
+        assert_eq!(p1.lerp(p2, 0.5), Point::new(2, 0));
+        assert_eq!(p1.lerp(p2, 0.25), Point::new(1, 5));
+    }
+
     #[test]
     fn point_rotate() {
         let o1 = Point::new(0, 0);
@@ -182,5 +257,25 @@ mod tests {
         let o2 = Point::new(1, 1);
         assert_eq!(Point::new(2, 3).rotate(o2, 90), Point::new(3, 0));
         assert_eq!(Point::new(-2, 3).rotate(o2, 90), Point::new(3, 4));
+
+        assert_eq!(Point::new(1, 0).rotate(o1, -90), Point::new(0, 1));
+        assert_eq!(Point::new(1, 0).rotate(o1, 450), Point::new(0, -1));
+
+        assert_eq!(Point::new(10, 0).rotate(o1, 45), Point::new(7, -7));
+        assert_eq!(Point::new(0, -10).rotate(o1, 45), Point::new(-7, -7));
+        assert_eq!(Point::new(10, 0).rotate(o1, 135), Point::new(-7, -7));
+        assert_eq!(Point::new(10, 0).rotate(o1, 315), Point::new(7, 7));
+        assert_eq!(Point::new(10, 0).rotate(o1, 30), Point::new(9, -5));
+
+        // Diagonals round to the nearest pixel without floating point.
+        assert_eq!(Point::new(1, 0).rotate(o1, 45), Point::new(1, -1));
+        assert_eq!(Point::new(3, 4).rotate(o1, 225), Point::new(-5, -1));
+        assert_eq!(Point::new(1000, 0).rotate(o1, 45), Point::new(707, -707));
+        assert_eq!(Point::new(1, 0).rotate(o1, -45), Point::new(1, 1));
+        assert_eq!(Point::new(17, -3).rotate(o1, 45 * 9), Point::new(10, -14));
+        for v in -2000..2000 {
+            let expected = (v as f64 / 2f64.sqrt()).round() as i32;
+            assert_eq!(div_sqrt2(v), expected, "{}", v);
+        }
     }
 }
@@ -1,23 +1,38 @@
+use serde::{Deserialize, Serialize};
 use std::cmp::{max, min};
 use std::ops::{Add, Sub};
 
+use crate::fixed::Fixed;
 use crate::point::Point;
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub struct Rect {
-    pub lo: Point,
-    pub hi: Point, // exclusive
+// Whole pixels by default, like Point.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Rect<T = i32> {
+    pub lo: Point<T>,
+    pub hi: Point<T>, // exclusive
 }
 
 fn clamp<T: Ord>(x: T, v0: T, v1: T) -> T {
     min(max(x, v0), v1)
 }
 
-impl Rect {
-    pub fn new(lo: Point, hi: Point) -> Rect {
+impl<T> Rect<T> {
+    pub fn new(lo: Point<T>, hi: Point<T>) -> Rect<T> {
         Rect { lo, hi }
     }
+}
+
+impl<T: Copy + PartialOrd> Rect<T> {
+    pub fn is_empty(self) -> bool {
+        self.hi.x <= self.lo.x || self.hi.y <= self.lo.y
+    }
+
+    pub fn has_intersection(self, r: Rect<T>) -> bool {
+        !(self.hi.x <= r.lo.x || self.hi.y <= r.lo.y || r.hi.x <= self.lo.x || r.hi.y <= self.lo.y)
+    }
+}
 
+impl Rect {
     pub fn new_with_size(x: i32, y: i32, width: i32, height: i32) -> Rect {
         let lo = Point::new(x, y);
         let hi = lo + Point::new(width, height) - 1;
@@ -61,6 +76,8 @@ impl Rect {
         )
     }
 
+    // Rotates the rect's corners and returns their bounding rect, which
+    // is the rotated rect itself for right angles.
     pub fn rotate(self, origin: Point, angle: i32 /* degrees CCW */) -> Rect {
         // Find the four corners of the rectangle.
 
@@ -68,25 +85,28 @@ impl Rect {
         // coordinates will be off if one of these gets swapped into a
         // low position. Shift back afterwards.
         let interior = self.grow(-1);
-        let upper_left = interior.lo;
-        let upper_right = (interior.hi.x, interior.lo.y).into();
-        let lower_left = (interior.lo.x, interior.hi.y).into();
-        let lower_right = interior.hi;
-
-        // Figure out which points to rotate based on angle.
-        let (lo, hi) = match angle {
-            0 => (upper_left, lower_right),
-            90 => (upper_right, lower_left),
-            180 => (lower_right, upper_left),
-            270 => (lower_left, upper_right),
-            _ => unimplemented!(),
-        };
-
-        Rect::new(lo.rotate(origin, angle), hi.rotate(origin, angle)).grow(1)
+        let corners = [
+            interior.lo,
+            (interior.hi.x, interior.lo.y).into(),
+            (interior.lo.x, interior.hi.y).into(),
+            interior.hi,
+        ];
+
+        let rotated: Vec<Point> = corners.iter().map(|p| p.rotate(origin, angle)).collect();
+        let lo = rotated[1..].iter().fold(rotated[0], |lo, p| {
+            Point::new(min(lo.x, p.x), min(lo.y, p.y))
+        });
+        let hi = rotated[1..].iter().fold(rotated[0], |hi, p| {
+            Point::new(max(hi.x, p.x), max(hi.y, p.y))
+        });
+
+        Rect::new(lo, hi).grow(1)
     }
 
-    pub fn has_intersection(self, r: Rect) -> bool {
-        !(self.hi.x <= r.lo.x || self.hi.y <= r.lo.y || r.hi.x <= self.lo.x || r.hi.y <= self.lo.y)
+    // Interpolate position between two rects of the same size.
+    pub fn lerp(self, other: Rect, alpha: f32) -> Rect {
+        let lo = self.lo.lerp(other.lo, alpha);
+        Rect::new(lo, lo + other.size())
     }
 }
 
@@ -144,18 +164,34 @@ impl From<((i32, i32), Point)> for Rect {
     }
 }
 
-impl Add<Point> for Rect {
-    type Output = Rect;
+impl Rect<Fixed> {
+    // The smallest whole-pixel rect covering this one.
+    pub fn pixels(self) -> Rect {
+        Rect::new(
+            self.lo.floor(),
+            Point::new(self.hi.x.ceil(), self.hi.y.ceil()),
+        )
+    }
+}
+
+impl From<Rect> for Rect<Fixed> {
+    fn from(r: Rect) -> Rect<Fixed> {
+        Rect::new(r.lo.into(), r.hi.into())
+    }
+}
+
+impl<T: Add<Output = T> + Copy> Add<Point<T>> for Rect<T> {
+    type Output = Rect<T>;
 
-    fn add(self, other: Point) -> Rect {
+    fn add(self, other: Point<T>) -> Rect<T> {
         Rect::new(self.lo + other, self.hi + other)
     }
 }
 
-impl Sub<Point> for Rect {
-    type Output = Rect;
+impl<T: Sub<Output = T> + Copy> Sub<Point<T>> for Rect<T> {
+    type Output = Rect<T>;
 
-    fn sub(self, other: Point) -> Rect {
+    fn sub(self, other: Point<T>) -> Rect<T> {
         Rect::new(self.lo - other, self.hi - other)
     }
 }
@@ -200,6 +236,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rect_lerp() {
+        let r1 = Rect::new_with_size(0, 0, 4, 4);
+        let r2 = Rect::new_with_size(10, -10, 4, 4);
+        assert_eq!(r1.lerp(r2, 0.0), r1);
+        assert_eq!(r1.lerp(r2, 1.0), r2);
+        assert_eq!(r1.lerp(r2, 0.5), Rect::new_with_size(5, -5, 4, 4));
+    }
+
+    #[test]
+    fn rect_fixed() {
+        let r: Rect<Fixed> = Rect::new(Point::new(0, 0), Point::new(4, 4)).into();
+        let half = Fixed::from_ratio(1, 2);
+        let moved = r + Point::new(half, -half);
+        assert!(moved.has_intersection(r));
+        assert!(!moved.is_empty());
+        assert_eq!(moved.pixels(), Rect::new(Point::new(0, -1), Point::new(5, 4)));
+        assert_eq!((moved - Point::new(half, -half)).pixels(), r.pixels());
+    }
+
     #[test]
     fn rect_rotate() {
         let r1 = Rect::new(Point::new(-1, -1), Point::new(2, 2));
@@ -230,5 +286,20 @@ mod tests {
             r3.rotate(o1, 90),
             Rect::new(Point::new(-1, -2), Point::new(4, 2))
         );
+        assert_eq!(r3.rotate(o1, -270), r3.rotate(o1, 90));
+        assert_eq!(r3.rotate(o1, 810), r3.rotate(o1, 90));
+
+        // Other angles give the bounding rect.
+        let r4 = Rect::new(Point::new(0, -10), Point::new(1, 0));
+        assert_eq!(
+            r4.rotate(o1, 45),
+            Rect::new(Point::new(-7, -7), Point::new(0, 0))
+        );
+        assert_eq!(
+            r4.rotate(o1, 315),
+            Rect::new(Point::new(1, -7), Point::new(8, 0))
+        );
+        assert_eq!(r1.rotate(o1, 45), r1);
+        assert_eq!(r1.rotate(o1, 10), r1);
     }
 }
@@ -0,0 +1,377 @@
+use sdl2::render::{Canvas, RenderTarget};
+use std::cmp::{max, min};
+use std::path::Path;
+
+use crate::error::Error;
+use crate::font::{glyph, text_size, GLYPH_HEIGHT, GLYPH_WIDTH, SPACING};
+use crate::framebuffer::Framebuffer;
+use crate::point::Point;
+use crate::rect::Rect;
+use crate::scene::Scene;
+use crate::sim::World;
+use crate::state::{join, EntityKind};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b, a: 255 }
+    }
+}
+
+impl From<Color> for sdl2::pixels::Color {
+    fn from(c: Color) -> sdl2::pixels::Color {
+        sdl2::pixels::Color::RGBA(c.r, c.g, c.b, c.a)
+    }
+}
+
+// Drawing operations needed by the game, so that frames can be drawn
+// to a window or to memory alike.
+pub trait Renderer {
+    fn set_color(&mut self, color: Color);
+    fn clear(&mut self);
+    fn fill_rect(&mut self, r: Rect) -> Result<(), Error>;
+}
+
+impl<T: RenderTarget> Renderer for Canvas<T> {
+    fn set_color(&mut self, color: Color) {
+        self.set_draw_color(color);
+    }
+
+    fn clear(&mut self) {
+        Canvas::clear(self);
+    }
+
+    fn fill_rect(&mut self, r: Rect) -> Result<(), Error> {
+        // SDL rounds empty rects up to 1x1, so skip them entirely.
+        if r.is_empty() {
+            return Ok(());
+        }
+        let r: sdl2::rect::Rect = r.into();
+        Canvas::fill_rect(self, r)?;
+        Ok(())
+    }
+}
+
+// Each player's color at full power, fading to white as power runs
+// out. Players beyond these reuse them from the start.
+const PLAYER_COLORS: [Color; 4] = [
+    Color::rgb(0, 0, 255),
+    Color::rgb(160, 0, 160),
+    Color::rgb(0, 128, 128),
+    Color::rgb(160, 96, 0),
+];
+
+fn player_color(index: usize, power: i32) -> Color {
+    let base = PLAYER_COLORS[index % PLAYER_COLORS.len()];
+    let fade = |c: u8| (c as i32 + (255 - c as i32) * (8 - min(power, 8)) / 8) as u8;
+    Color::rgb(fade(base.r), fade(base.g), fade(base.b))
+}
+
+// Alpha is how far we are between the previous step and the current
+// one, used to smooth out motion when rendering faster than we tick.
+pub fn render<R: Renderer>(renderer: &mut R, world: &World, alpha: f32) -> Result<(), Error> {
+    let state = &world.state;
+    // Lighter the more the players still alive have scored between them,
+    // and red once they're all dead.
+    if world.is_over() {
+        renderer.set_color(Color::rgb(255, 0, 0));
+    } else {
+        let score: i32 = world
+            .living_players()
+            .filter_map(|id| state.stats.get(id))
+            .map(|stats| stats.score)
+            .sum();
+        let c = (255 * min(score, 20) / 20) as u8;
+        renderer.set_color(Color::rgb(c, c, c));
+    }
+    renderer.clear();
+
+    renderer.set_color(Color::rgb(128, 96, 64));
+    for wall in state.tiles.walls() {
+        renderer.fill_rect(wall)?;
+    }
+
+    for (id, &kind, &hitbox) in join(&state.kind, &state.hitbox) {
+        let b = world.interpolated_hitbox(id, alpha).unwrap_or(hitbox);
+        let offset = b.lo - hitbox.lo;
+
+        match kind {
+            EntityKind::Player => {
+                let power = state.stats.get(id).map_or(0, |s| s.power);
+                let index = world.players.iter().position(|&p| p == id).unwrap_or(0);
+                renderer.set_color(player_color(index, power))
+            }
+            EntityKind::Monster => {
+                let agro = state.ai.get(id).map_or(0, |ai| ai.agro);
+                renderer.set_color(Color::rgb(
+                    255,
+                    (255 * (3 - min(agro / 320, 3)) / 3) as u8,
+                    (255 * (3 - min(agro / 320, 3)) / 3) as u8,
+                ))
+            }
+            EntityKind::Power => renderer.set_color(Color::rgb(0, 0, 255)),
+        }
+        renderer.fill_rect(b)?;
+        if kind == EntityKind::Player {
+            if let Some(&facing) = state.facing.get(id) {
+                renderer.set_color(Color::rgb(0, 255, 0));
+                let w = 4;
+                if !facing.is_diagonal() {
+                    // Mark the edge we're facing.
+                    let face: Rect = (b.index(-1, -1), b.index(1, -1) + (0, w)).into();
+                    let face = face.rotate(b.center(), facing.angle());
+                    renderer.fill_rect(face)?;
+                } else {
+                    // Mark the corner we're facing, along half of each
+                    // edge next to it.
+                    let step = facing.to_point();
+                    let corner = b.index(step.x, step.y);
+                    let center = b.center();
+                    let x = if step.x < 0 { b.lo.x } else { b.hi.x - w };
+                    let y = if step.y < 0 { b.lo.y } else { b.hi.y - w };
+                    let (x0, x1) = (min(corner.x, center.x), max(corner.x, center.x));
+                    let (y0, y1) = (min(corner.y, center.y), max(corner.y, center.y));
+                    renderer.fill_rect(Rect::new(Point::new(x0, y), Point::new(x1, y + w)))?;
+                    renderer.fill_rect(Rect::new(Point::new(x, y0), Point::new(x + w, y1)))?;
+                }
+            }
+
+            if let Some(attack) = state.attack.get(id) {
+                renderer.set_color(Color::rgb(255, 255, 0));
+                renderer.fill_rect(attack.hitbox + offset)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Draws a line of text with its top left corner at the given point,
+// each pixel of the font scale pixels across.
+pub fn draw_text<R: Renderer>(
+    renderer: &mut R,
+    text: &str,
+    at: Point,
+    scale: i32,
+) -> Result<(), Error> {
+    for (i, c) in text.chars().enumerate() {
+        let x = at.x + i as i32 * (GLYPH_WIDTH + SPACING) * scale;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            let y = at.y + row as i32 * scale;
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                    let lo = Point::new(x + column * scale, y);
+                    renderer.fill_rect(Rect::new(lo, lo + scale))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Draws lines of text, each at the given scale or smaller if that's too
+// wide, centered on the arena on a black band.
+fn draw_lines<R: Renderer>(
+    renderer: &mut R,
+    arena: Rect,
+    lines: &[(String, i32)],
+) -> Result<(), Error> {
+    let width = arena.width() as i32;
+    let lines: Vec<_> = lines
+        .iter()
+        .map(|(text, scale)| {
+            let mut scale = *scale;
+            while scale > 1 && text_size(text, scale).0 > width - 2 * GLYPH_HEIGHT * scale {
+                scale -= 1;
+            }
+            (text, scale)
+        })
+        .collect();
+
+    // Each line is followed by a gap half its height.
+    let gap = |scale: i32| GLYPH_HEIGHT * scale / 2;
+    let height: i32 = lines.iter().map(|&(_, s)| GLYPH_HEIGHT * s + gap(s)).sum();
+    let center = arena.center();
+    let top = center.y - height / 2;
+    let margin = GLYPH_HEIGHT * lines.first().map_or(1, |&(_, s)| s);
+    renderer.set_color(Color::rgb(0, 0, 0));
+    renderer.fill_rect(Rect::new(
+        Point::new(arena.lo.x, top - margin),
+        Point::new(arena.hi.x, top + height + margin),
+    ))?;
+
+    renderer.set_color(Color::rgb(255, 255, 255));
+    let mut y = top;
+    for (text, scale) in lines {
+        let (w, h) = text_size(text, scale);
+        draw_text(renderer, text, Point::new(center.x - w / 2, y), scale)?;
+        y += h + gap(scale);
+    }
+    Ok(())
+}
+
+// Renders whatever the scene shows: the game itself while playing, with
+// text over it when paused or over, and a title screen before it starts.
+pub fn render_scene<R: Renderer>(
+    renderer: &mut R,
+    scene: Scene,
+    world: &World,
+    alpha: f32,
+) -> Result<(), Error> {
+    let line = |text: &str, scale| (text.to_string(), scale);
+    match scene {
+        Scene::Title => {
+            renderer.set_color(Color::rgb(0, 0, 0));
+            renderer.clear();
+            draw_lines(
+                renderer,
+                world.arena,
+                &[line("DEMO", 8), line("PRESS ATTACK TO START", 2)],
+            )
+        }
+        Scene::Playing => render(renderer, world, alpha),
+        Scene::Paused => {
+            render(renderer, world, alpha)?;
+            draw_lines(
+                renderer,
+                world.arena,
+                &[line("PAUSED", 4), line("PRESS PAUSE TO RESUME", 2)],
+            )
+        }
+        Scene::GameOver => {
+            render(renderer, world, alpha)?;
+            let mut lines = vec![
+                line("GAME OVER", 4),
+                (format!("SCORE: {}", world.score()), 3),
+            ];
+            if world.players.len() > 1 {
+                for (i, &player) in world.players.iter().enumerate() {
+                    let score = world.stats(player).map_or(0, |s| s.score);
+                    lines.push((format!("PLAYER {}: {}", i + 1, score), 2));
+                }
+            }
+            lines.push(line("PRESS ATTACK TO PLAY AGAIN", 2));
+            draw_lines(renderer, world.arena, &lines)
+        }
+    }
+}
+
+// Render the world to an image file the size of the arena, without
+// needing a window.
+pub fn capture<P: AsRef<Path>>(world: &World, path: P) -> Result<(), Error> {
+    let size = world.arena.hi - world.arena.lo;
+    let mut fb = Framebuffer::new(size.x as u32, size.y as u32);
+    render(&mut fb, world, 1.0)?;
+    fb.write_ppm(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::Control;
+    use crate::level::Level;
+    use crate::state::Stats;
+
+    #[test]
+    fn render_framebuffer() {
+        let mut world = World::new(&Level::default(), 0);
+        world.step(&[Control::default()]);
+
+        let mut fb = Framebuffer::new(800, 600);
+        render(&mut fb, &world, 1.0).unwrap();
+
+        let player = world.state.hitbox[world.players[0]];
+        let center = player.center();
+        assert_eq!(fb.pixel(0, 599), Color::rgb(0, 0, 0));
+        assert_eq!(
+            fb.pixel(center.x as u32, center.y as u32),
+            Color::rgb(255, 255, 255)
+        );
+        assert_eq!(
+            fb.pixel(center.x as u32, player.lo.y as u32),
+            Color::rgb(0, 255, 0)
+        );
+
+        // Once the player dies, the background turns red.
+        world.state.despawn(world.players[0]);
+        render(&mut fb, &world, 1.0).unwrap();
+        assert_eq!(fb.pixel(0, 599), Color::rgb(255, 0, 0));
+    }
+
+    #[test]
+    fn render_co_op() {
+        let mut world = World::with_players(&Level::default(), 0, 2);
+        let (first, second) = (world.players[0], world.players[1]);
+        world.state.stats[first].score = 5;
+        world.state.stats[second] = Stats { score: 5, power: 8 };
+        world.step(&[]);
+
+        let mut fb = Framebuffer::new(800, 600);
+        render(&mut fb, &world, 1.0).unwrap();
+        assert_eq!(fb.pixel(0, 599), Color::rgb(127, 127, 127));
+        let center = world.state.hitbox[second].center();
+        assert_eq!(
+            fb.pixel(center.x as u32, center.y as u32),
+            Color::rgb(160, 0, 160)
+        );
+
+        // Only the players still alive count towards the background, and
+        // it stays that way until they're all dead.
+        world.state.despawn(first);
+        render(&mut fb, &world, 1.0).unwrap();
+        assert_eq!(fb.pixel(0, 599), Color::rgb(63, 63, 63));
+    }
+
+    #[test]
+    fn render_text() {
+        let mut fb = Framebuffer::new(20, 10);
+        fb.set_color(Color::rgb(255, 255, 255));
+        draw_text(&mut fb, "T1", Point::new(1, 0), 2).unwrap();
+
+        // The top of the T, and not the gaps either side of its stem.
+        let white = Color::rgb(255, 255, 255);
+        let black = Color::rgb(0, 0, 0);
+        assert_eq!(fb.pixel(1, 0), white);
+        assert_eq!(fb.pixel(6, 1), white);
+        assert_eq!(fb.pixel(7, 0), black);
+        assert_eq!(fb.pixel(1, 2), black);
+        assert_eq!(fb.pixel(3, 9), white);
+
+        // The 1 starts after the spacing, with its top left corner empty.
+        assert_eq!(fb.pixel(9, 0), black);
+        assert_eq!(fb.pixel(11, 0), white);
+        assert_eq!(fb.pixel(9, 2), white);
+    }
+
+    #[test]
+    fn render_scenes() {
+        let mut world = World::new(&Level::default(), 0);
+        let mut fb = Framebuffer::new(800, 600);
+        let center = world.state.hitbox[world.players[0]].center();
+        let player_pixel = |fb: &Framebuffer| fb.pixel(center.x as u32, center.y as u32);
+
+        // The title screen hides the game, and pausing covers the middle
+        // of it.
+        render_scene(&mut fb, Scene::Title, &world, 1.0).unwrap();
+        assert_eq!(fb.pixel(0, 599), Color::rgb(0, 0, 0));
+        assert_ne!(player_pixel(&fb), Color::rgb(255, 255, 255));
+        render_scene(&mut fb, Scene::Playing, &world, 1.0).unwrap();
+        assert_eq!(player_pixel(&fb), Color::rgb(255, 255, 255));
+        render_scene(&mut fb, Scene::Paused, &world, 1.0).unwrap();
+        assert_eq!(fb.pixel(0, 300), Color::rgb(0, 0, 0));
+        assert_eq!(fb.pixel(0, 0), Color::rgb(0, 0, 0));
+
+        // Game over leaves the red background showing around the text.
+        world.state.despawn(world.players[0]);
+        render_scene(&mut fb, Scene::GameOver, &world, 1.0).unwrap();
+        assert_eq!(fb.pixel(0, 0), Color::rgb(255, 0, 0));
+        assert_eq!(fb.pixel(0, 300), Color::rgb(0, 0, 0));
+    }
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::control::Control;
+use crate::error::Error;
+use crate::level::Level;
+
+// A recorded session. The simulation is driven only by the level, the
+// seed, the number of players and their per-frame controls, so this is
+// enough to reproduce it exactly.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Replay {
+    pub seed: u64,
+    pub level: Level,
+    pub players: usize,
+    // Each frame has a control for every player, in order.
+    pub frames: Vec<Vec<Control>>,
+}
+
+impl Replay {
+    pub fn new(seed: u64, level: Level, players: usize) -> Replay {
+        Replay {
+            seed,
+            level,
+            players,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, controls: &[Control]) {
+        self.frames.push(controls.to_vec());
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Replay, Error> {
+        let text = fs::read_to_string(path)?;
+        Ok(ron::from_str(&text)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        // One frame per line keeps replays readable and diffable.
+        let config = ron::ser::PrettyConfig::new().depth_limit(2);
+        let text = ron::ser::to_string_pretty(self, config)?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::direction::Direction;
+    use crate::sim::World;
+
+    #[test]
+    fn replay_round_trip() {
+        let mut replay = Replay::new(7, Level::default(), 2);
+        let mut world = World::with_players(&replay.level, 7, 2);
+        let mut controls = vec![Control::default(); 2];
+        for frame in 0..500 {
+            for (i, control) in controls.iter_mut().enumerate() {
+                let frame = frame + i * 17;
+                control.left_right_input = [1, 0, -1, 0][(frame / 40) % 4];
+                control.up_down_input = [0, 1, 0, -1][(frame / 60) % 4];
+                control.facing_input = Direction::ALL[(frame / 30) % 8];
+                control.attack_input = frame % 25 == 0;
+            }
+            world.step(&controls);
+            replay.record(&controls);
+        }
+
+        let path = std::env::temp_dir().join("game_replay_round_trip.ron");
+        replay.save(&path).unwrap();
+        let loaded = Replay::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, replay);
+
+        let mut replayed = World::with_players(&loaded.level, loaded.seed, loaded.players);
+        for controls in &loaded.frames {
+            replayed.step(controls);
+        }
+        assert!(world.state.hitbox == replayed.state.hitbox);
+        assert!(world.state.stats == replayed.state.stats);
+    }
+}
@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+
+// Small seedable pseudo-random number generator (SplitMix64). Runs must
+// be exactly reproducible from a seed, so this is implemented here
+// rather than relying on a library whose output may change between
+// versions.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniformly distributed value in lo..hi (exclusive).
+    pub fn range(&mut self, lo: i32, hi: i32) -> i32 {
+        assert!(lo < hi);
+        let span = (hi as i64 - lo as i64) as u64;
+        (lo as i64 + (self.next_u64() % span) as i64) as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rng_deterministic() {
+        let mut r1 = Rng::new(1234);
+        let mut r2 = Rng::new(1234);
+        for _ in 0..100 {
+            assert_eq!(r1.next_u64(), r2.next_u64());
+        }
+
+        let mut r3 = Rng::new(1235);
+        assert_ne!(Rng::new(1234).next_u64(), r3.next_u64());
+    }
+
+    #[test]
+    fn rng_range() {
+        let mut r = Rng::new(0);
+        let mut seen = [false; 7];
+        for _ in 0..1000 {
+            let x = r.range(-3, 4);
+            assert!((-3..4).contains(&x));
+            seen[(x + 3) as usize] = true;
+        }
+        assert!(seen.iter().all(|&s| s));
+    }
+}
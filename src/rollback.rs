@@ -0,0 +1,436 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+use crate::control::Control;
+use crate::error::Error;
+use crate::event::Events;
+use crate::net::{InputSync, Transport};
+use crate::sim::{Snapshot, World};
+
+// Networked play with rollback. Rather than waiting on the other peers
+// the way lockstep does, frames run straight away, guessing that each
+// remote player keeps doing what they last did. A snapshot of the world
+// is kept from before every frame that ran on a guess, and once the
+// real input arrives and turns out different, the world goes back to
+// the snapshot and runs the frames since again.
+//
+// Only so many frames can run ahead of the confirmed ones, after which
+// this waits like lockstep does, so a peer that drops out doesn't leave
+// the others guessing forever.
+//
+// The world is passed in rather than owned, so the caller can render it.
+// Its events may be from a frame run on a guess, so the ones to go by
+// are those of confirmed frames, from take_events.
+pub struct Rollback<T> {
+    sync: InputSync<T>,
+    max_prediction: u64,
+    // The next frame to run, and the first one not run with every
+    // player's real input.
+    frame: u64,
+    confirmed: u64,
+    // For each frame from the first unconfirmed one on, the world at
+    // its start, the controls it ran with and what happened in it.
+    snapshots: VecDeque<Snapshot>,
+    ran: VecDeque<Vec<Control>>,
+    events: VecDeque<Events>,
+    // What happened in the frames confirmed since take_events.
+    confirmed_events: Vec<Events>,
+    // Checksums of the world after each confirmed frame: ours, and those
+    // the other peers have sent that we can't check yet.
+    checksums: Vec<u64>,
+    remote_checksums: BTreeMap<u64, u64>,
+    desync: Option<u64>,
+    resimulated: u64,
+}
+
+impl<T: Transport> Rollback<T> {
+    // Takes a transport to each other peer, with the player it's for.
+    pub fn new(
+        local: usize,
+        players: usize,
+        delay: u64,
+        max_prediction: u64,
+        peers: Vec<(usize, T)>,
+    ) -> Rollback<T> {
+        assert!(max_prediction > 0);
+        Rollback {
+            sync: InputSync::new(local, players, delay, peers),
+            max_prediction,
+            frame: 0,
+            confirmed: 0,
+            snapshots: VecDeque::new(),
+            ran: VecDeque::new(),
+            events: VecDeque::new(),
+            confirmed_events: Vec::new(),
+            checksums: Vec::new(),
+            remote_checksums: BTreeMap::new(),
+            desync: None,
+            resimulated: 0,
+        }
+    }
+
+    pub fn local_player(&self) -> usize {
+        self.sync.local_player()
+    }
+
+    // The next frame to run.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // Frames before this one ran with every player's real input, and
+    // won't change.
+    pub fn confirmed_frame(&self) -> u64 {
+        self.confirmed
+    }
+
+    // The controls a confirmed frame ran with.
+    pub fn confirmed_controls(&self, frame: u64) -> Option<Vec<Control>> {
+        if frame < self.confirmed {
+            self.sync.frame(frame)
+        } else {
+            None
+        }
+    }
+
+    // The events of each frame confirmed since this was last called, in
+    // order. Unlike the world's own, these never get undone.
+    pub fn take_events(&mut self) -> Vec<Events> {
+        std::mem::take(&mut self.confirmed_events)
+    }
+
+    // Our checksum of the world after each confirmed frame.
+    pub fn checksums(&self) -> &[u64] {
+        &self.checksums
+    }
+
+    // The first frame found after which another peer's world differed
+    // from ours. Every peer runs the same frames with the same input, so
+    // this means the simulation isn't deterministic, or the peers didn't
+    // start out the same.
+    pub fn desync(&self) -> Option<u64> {
+        self.desync
+    }
+
+    // How many frames have had to run again so far.
+    pub fn resimulated(&self) -> u64 {
+        self.resimulated
+    }
+
+    // Whether the other peers have all the input we've run with, so it's
+    // safe to leave once a quit has.
+    pub fn is_acked(&self) -> bool {
+        self.sync.is_acked()
+    }
+
+    // How long since we've heard from the quietest peer.
+    pub fn silence(&self) -> Duration {
+        self.sync.silence()
+    }
+
+    // Takes in what the other peers have sent, rolling back and
+    // confirming frames as needed, and sends our unacknowledged input,
+    // without running any new frames. For when we're done running them
+    // but the other peers might not be.
+    pub fn poll(&mut self, world: &mut World) -> Result<(), Error> {
+        let checksums = self.sync.receive()?;
+        self.remote_checksums.extend(checksums);
+        self.reconcile(world);
+        self.send()
+    }
+
+    // Takes in what the other peers have sent, rolling back if needed,
+    // then runs the next frame with the local input and sends it off.
+    // Returns whether the frame ran; if it had to wait, the input wasn't
+    // taken. Call this once a tick.
+    pub fn advance(&mut self, world: &mut World, control: &Control) -> Result<bool, Error> {
+        let checksums = self.sync.receive()?;
+        self.remote_checksums.extend(checksums);
+        self.reconcile(world);
+
+        let ran = self.frame < self.confirmed + self.max_prediction;
+        if ran {
+            self.sync.add_local_input(control);
+            self.step(world);
+            self.confirm(world);
+        }
+
+        self.send()?;
+        Ok(ran)
+    }
+
+    // Our input, with the checksum of the latest confirmed frame.
+    fn send(&mut self) -> Result<(), Error> {
+        let checksum = self.checksums.last().map(|&c| (self.confirmed - 1, c));
+        self.sync.send(checksum)
+    }
+
+    // A frame's controls: the real ones where they've arrived, and
+    // otherwise a guess at the same as the player's latest, without any
+    // presses, since those only last a frame.
+    fn controls(&self, frame: u64) -> Vec<Control> {
+        (0..self.sync.players())
+            .map(|player| match self.sync.get(player, frame) {
+                Some(control) => control.clone(),
+                None => {
+                    let mut guess = self.sync.last(player).cloned().unwrap_or_default();
+                    guess.attack_input = false;
+                    guess.switch_weapon_input = 0;
+                    guess
+                }
+            })
+            .collect()
+    }
+
+    fn step(&mut self, world: &mut World) {
+        let controls = self.controls(self.frame);
+        self.snapshots.push_back(world.snapshot());
+        world.step(&controls);
+        self.ran.push_back(controls);
+        self.events.push_back(world.state.events.clone());
+        self.frame += 1;
+    }
+
+    // Goes back and runs again from the first frame whose guess turned
+    // out wrong, if any.
+    fn reconcile(&mut self, world: &mut World) {
+        let wrong = (self.confirmed..self.frame).find(|&frame| {
+            let ran = &self.ran[(frame - self.confirmed) as usize];
+            ran.iter()
+                .enumerate()
+                .any(|(player, control)| self.sync.get(player, frame).is_some_and(|c| c != control))
+        });
+        if let Some(wrong) = wrong {
+            let index = (wrong - self.confirmed) as usize;
+            world.restore(&self.snapshots[index]);
+            self.snapshots.truncate(index);
+            self.ran.truncate(index);
+            self.events.truncate(index);
+            let end = self.frame;
+            self.frame = wrong;
+            while self.frame < end {
+                self.step(world);
+            }
+            self.resimulated += end - wrong;
+        }
+        self.confirm(world)
+    }
+
+    // Moves past the frames that now have every player's real input,
+    // dropping their snapshots and checking their checksums.
+    fn confirm(&mut self, world: &World) {
+        while self.confirmed < self.frame && self.sync.frame(self.confirmed).is_some() {
+            self.snapshots.pop_front();
+            self.ran.pop_front();
+            self.confirmed_events.extend(self.events.pop_front());
+            let after = match self.snapshots.front() {
+                Some(snapshot) => snapshot.checksum(),
+                None => world.checksum(),
+            };
+            self.checksums.push(after);
+            self.confirmed += 1;
+        }
+
+        let confirmed = self.confirmed;
+        let remote: Vec<_> = self
+            .remote_checksums
+            .range(..confirmed)
+            .map(|(&f, &c)| (f, c))
+            .collect();
+        for (frame, checksum) in remote {
+            self.remote_checksums.remove(&frame);
+            if self.checksums[frame as usize] != checksum && self.desync.is_none_or(|d| frame < d) {
+                self.desync = Some(frame);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::direction::Direction;
+    use crate::level::Level;
+    use crate::net::{LossyTransport, MemoryNetwork, MemoryTransport};
+
+    type Session = Rollback<LossyTransport<MemoryTransport>>;
+
+    // Some input that changes from frame to frame, differently for each
+    // player.
+    fn scripted(player: usize, frame: usize) -> Control {
+        let frame = frame + player * 23;
+        let mut control = Control::default();
+        control.left_right_input = [1, 0, -1, 0][(frame / 20) % 4];
+        control.up_down_input = [0, 1, 0, -1][(frame / 30) % 4];
+        control.facing_input = Direction::ALL[(frame / 15) % 8];
+        control.attack_input = frame.is_multiple_of(10);
+        control
+    }
+
+    // Two peers with the given latency in frames, each losing and
+    // reordering some of what it sends. Runs them with the scripted
+    // input until both have confirmed the given number of frames.
+    struct Harness {
+        network: MemoryNetwork,
+        sessions: Vec<Session>,
+        worlds: Vec<World>,
+        inputs: Vec<usize>,
+        events: Vec<Vec<Events>>,
+    }
+
+    impl Harness {
+        fn new(latency: u64, loss: i32, reorder: i32, seeds: [u64; 2]) -> Harness {
+            let network = MemoryNetwork::default();
+            let (a, b) = network.link(latency);
+            let lossy = |t, seed| LossyTransport::new(t, seed, loss, reorder);
+            Harness {
+                sessions: vec![
+                    Rollback::new(0, 2, 1, 8, vec![(1, lossy(a, 1))]),
+                    Rollback::new(1, 2, 1, 8, vec![(0, lossy(b, 2))]),
+                ],
+                network,
+                worlds: seeds
+                    .iter()
+                    .map(|&seed| World::with_players(&Level::default(), seed, 2))
+                    .collect(),
+                inputs: vec![0; 2],
+                events: vec![Vec::new(); 2],
+            }
+        }
+
+        fn run(&mut self, frames: u64) {
+            for _ in 0..10 * frames {
+                for i in 0..2 {
+                    let control = scripted(i, self.inputs[i]);
+                    let session = &mut self.sessions[i];
+                    if session.advance(&mut self.worlds[i], &control).unwrap() {
+                        self.inputs[i] += 1;
+                    }
+                    self.events[i].extend(session.take_events());
+                }
+                self.network.tick();
+                if self.sessions.iter().all(|s| s.confirmed_frame() >= frames) {
+                    return;
+                }
+            }
+            panic!("peers stopped making progress");
+        }
+
+        // What the game should have been: the confirmed frames, run
+        // straight through, and what happened in each.
+        fn expected(&self, frames: u64) -> (World, Vec<Events>) {
+            let mut world = World::with_players(&Level::default(), 0, 2);
+            let mut events = Vec::new();
+            for frame in 0..frames {
+                world.step(&self.sessions[0].confirmed_controls(frame).unwrap());
+                events.push(world.state.events.clone());
+            }
+            (world, events)
+        }
+    }
+
+    fn check(harness: &Harness, frames: u64) {
+        let (a, b) = (&harness.sessions[0], &harness.sessions[1]);
+        assert_eq!(a.desync(), None);
+        assert_eq!(b.desync(), None);
+        let n = frames as usize;
+        assert_eq!(a.checksums()[..n], b.checksums()[..n]);
+        for frame in 0..frames {
+            assert_eq!(a.confirmed_controls(frame), b.confirmed_controls(frame));
+        }
+        let (expected, events) = harness.expected(frames);
+        assert_eq!(a.checksums()[n - 1], expected.checksum());
+
+        // Events come out once for each frame, as they would have without
+        // any guessing, however many times the frame actually ran.
+        assert!(events.iter().any(|e| !e.is_empty()));
+        assert_eq!(harness.events[0][..n], events[..]);
+        assert_eq!(harness.events[1][..n], events[..]);
+
+        // The delay shows up as idle frames at the start, after which
+        // each player's input runs in order.
+        assert_eq!(a.confirmed_controls(0), Some(vec![Control::default(); 2]));
+        let controls = a.confirmed_controls(frames - 1).unwrap();
+        assert_eq!(controls[1], scripted(1, n - 2));
+    }
+
+    #[test]
+    fn rollback_latency() {
+        let mut harness = Harness::new(4, 0, 0, [0, 0]);
+        harness.run(300);
+        check(&harness, 300);
+
+        // The guesses were wrong some of the time, and got fixed.
+        assert!(harness.sessions.iter().all(|s| s.resimulated() > 0));
+    }
+
+    #[test]
+    fn rollback_lossy() {
+        let mut harness = Harness::new(2, 25, 25, [0, 0]);
+        harness.run(300);
+        check(&harness, 300);
+    }
+
+    #[test]
+    fn rollback_no_latency() {
+        // Nothing ever has to be guessed.
+        let mut harness = Harness::new(0, 0, 0, [0, 0]);
+        harness.run(100);
+        check(&harness, 100);
+        assert!(harness.sessions.iter().all(|s| s.resimulated() == 0));
+    }
+
+    #[test]
+    fn rollback_desync() {
+        // Peers that started out differently are caught on the first
+        // frame whose checksums get compared.
+        let mut harness = Harness::new(2, 0, 0, [0, 1]);
+        harness.run(50);
+        assert_eq!(harness.sessions[0].desync(), Some(0));
+        assert_eq!(harness.sessions[1].desync(), Some(0));
+    }
+
+    #[test]
+    fn rollback_quit() {
+        // One peer runs as far ahead of the other as it's allowed to.
+        let mut harness = Harness::new(2, 0, 0, [0, 0]);
+        let idle = Control::default();
+        while harness.sessions[0]
+            .advance(&mut harness.worlds[0], &idle)
+            .unwrap()
+        {
+            harness.network.tick();
+        }
+        let mut quit = Control::default();
+        quit.quit_input = true;
+        assert!(!harness.sessions[0]
+            .advance(&mut harness.worlds[0], &quit)
+            .unwrap());
+
+        // Its quit goes in once the other catches up, and then keeps
+        // being sent until the other has it.
+        let mut queued = false;
+        for _ in 0..100 {
+            let (a, b) = harness.sessions.split_at_mut(1);
+            if queued {
+                a[0].poll(&mut harness.worlds[0]).unwrap();
+            } else {
+                queued = a[0].advance(&mut harness.worlds[0], &quit).unwrap();
+            }
+            b[0].advance(&mut harness.worlds[1], &idle).unwrap();
+            harness.network.tick();
+            if queued && a[0].is_acked() {
+                break;
+            }
+        }
+        assert!(queued && harness.sessions[0].is_acked());
+
+        let b = &mut harness.sessions[1];
+        for _ in 0..20 {
+            b.advance(&mut harness.worlds[1], &idle).unwrap();
+        }
+        let quits =
+            (0..b.confirmed_frame()).filter(|&f| b.confirmed_controls(f).unwrap()[0].quit_input);
+        assert_eq!(quits.count(), 1);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::sim::World;
+
+// What's on screen, and whether the game is running:
+//
+//   Title -> Playing <-> Paused
+//               |
+//               v
+//            GameOver -> Playing (a new game)
+//
+// The game only steps while Playing. Game over lasts only as long as the
+// game is: loading a save, or a rollback undoing the last death, goes
+// back to playing.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Scene {
+    Title,
+    Playing,
+    Paused,
+    GameOver,
+}
+
+// What the players asked for this frame that moves between scenes.
+// Start is pressing attack, which is what starts a game from the title
+// or game over screen.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SceneInput {
+    pub start: bool,
+    pub pause: bool,
+}
+
+impl Scene {
+    // The scene to go to from this one. Going from GameOver to Playing
+    // means the caller should start a new game.
+    pub fn next(self, world: &World, input: SceneInput) -> Scene {
+        match self {
+            Scene::Title if input.start => Scene::Playing,
+            Scene::Playing if world.is_over() => Scene::GameOver,
+            Scene::Playing if input.pause => Scene::Paused,
+            Scene::Paused if input.pause => Scene::Playing,
+            Scene::GameOver if input.start || !world.is_over() => Scene::Playing,
+            scene => scene,
+        }
+    }
+
+    pub fn is_running(self) -> bool {
+        self == Scene::Playing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::Level;
+
+    #[test]
+    fn scene_transitions() {
+        let mut world = World::new(&Level::default(), 0);
+        let none = SceneInput::default();
+        let start = SceneInput {
+            start: true,
+            pause: false,
+        };
+        let pause = SceneInput {
+            start: false,
+            pause: true,
+        };
+
+        assert_eq!(Scene::Title.next(&world, none), Scene::Title);
+        assert_eq!(Scene::Title.next(&world, pause), Scene::Title);
+        assert_eq!(Scene::Title.next(&world, start), Scene::Playing);
+
+        // Attacking doesn't leave the game, and pausing toggles.
+        assert_eq!(Scene::Playing.next(&world, start), Scene::Playing);
+        assert_eq!(Scene::Playing.next(&world, pause), Scene::Paused);
+        assert_eq!(Scene::Paused.next(&world, start), Scene::Paused);
+        assert_eq!(Scene::Paused.next(&world, pause), Scene::Playing);
+        assert_eq!(Scene::GameOver.next(&world, none), Scene::Playing);
+        assert!(Scene::Playing.is_running());
+        assert!(!Scene::Paused.is_running());
+
+        // The game is over once everyone's dead, even if they pause on
+        // the same frame, and it takes a press to go again.
+        world.state.despawn(world.players[0]);
+        assert_eq!(Scene::Playing.next(&world, none), Scene::GameOver);
+        assert_eq!(Scene::Playing.next(&world, pause), Scene::GameOver);
+        assert_eq!(Scene::GameOver.next(&world, none), Scene::GameOver);
+        assert_eq!(Scene::GameOver.next(&world, pause), Scene::GameOver);
+        assert_eq!(Scene::GameOver.next(&world, start), Scene::Playing);
+    }
+}
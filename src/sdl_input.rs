@@ -0,0 +1,166 @@
+use sdl2::controller::{self, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::{EventPump, GameControllerSubsystem};
+
+use crate::bindings::Bindings;
+use crate::control::{handle_input, Control};
+use crate::error::Error;
+use crate::input::{Axis, Button, ControllerId, InputEvent};
+
+// Turns SDL events into input events, and keeps the game controllers
+// open. SDL reports each controller as added when it's plugged in,
+// including those already plugged in when the subsystem starts, and as
+// removed when it's unplugged.
+//
+// Each controller belongs to one player: the first without one when
+// it's plugged in. The keyboard is shared by all of them.
+pub struct SdlInput {
+    subsystem: GameControllerSubsystem,
+    // Indexed by player.
+    controllers: Vec<Option<GameController>>,
+}
+
+impl SdlInput {
+    // Takes each player's bindings. Fails if any of them name a key SDL
+    // doesn't know, or the same key for two players.
+    pub fn new(
+        subsystem: GameControllerSubsystem,
+        bindings: &[Bindings],
+    ) -> Result<SdlInput, Error> {
+        for (key, _) in bindings.iter().flat_map(|b| b.iter()) {
+            if Keycode::from_name(key).is_none() {
+                return Err(Error::ConfigError(format!("unknown key {}", key)));
+            }
+        }
+        Bindings::validate_players(bindings)?;
+        Ok(SdlInput {
+            subsystem,
+            controllers: Vec::new(),
+        })
+    }
+
+    // Joysticks that SDL doesn't know how to map as a controller are
+    // ignored.
+    fn add_controller(&mut self, joystick_index: u32) {
+        if let Ok(controller) = self.subsystem.open(joystick_index) {
+            let id = controller.instance_id();
+            if self.player(id).is_some() {
+                return;
+            }
+            match self.controllers.iter().position(Option::is_none) {
+                Some(player) => self.controllers[player] = Some(controller),
+                None => self.controllers.push(Some(controller)),
+            }
+        }
+    }
+
+    fn remove_controller(&mut self, id: ControllerId) {
+        if let Some(player) = self.player(id) {
+            self.controllers[player] = None;
+        }
+    }
+
+    // The player a controller belongs to.
+    pub fn player(&self, id: ControllerId) -> Option<usize> {
+        self.controllers
+            .iter()
+            .position(|c| c.as_ref().is_some_and(|c| c.instance_id() == id))
+    }
+
+    // Returns the event along with the player it's for, or None if it's
+    // for every player.
+    pub fn translate(&mut self, event: Event) -> Option<(Option<usize>, InputEvent)> {
+        let event = match event {
+            Event::Quit { .. } => InputEvent::Quit,
+            Event::KeyDown {
+                keycode: Some(keycode),
+                repeat: false,
+                ..
+            } => InputEvent::KeyDown(keycode.name()),
+            Event::KeyUp {
+                keycode: Some(keycode),
+                ..
+            } => InputEvent::KeyUp(keycode.name()),
+            Event::ControllerButtonDown { which, button, .. } => {
+                InputEvent::ButtonDown(which, translate_button(button))
+            }
+            Event::ControllerButtonUp { which, button, .. } => {
+                InputEvent::ButtonUp(which, translate_button(button))
+            }
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => InputEvent::AxisMotion(which, translate_axis(axis), value),
+            Event::ControllerDeviceAdded { which, .. } => {
+                self.add_controller(which);
+                return None;
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                let player = self.player(which)?;
+                self.remove_controller(which);
+                return Some((Some(player), InputEvent::ControllerRemoved(which)));
+            }
+            _ => return None,
+        };
+        let player = match event {
+            InputEvent::ButtonDown(id, _)
+            | InputEvent::ButtonUp(id, _)
+            | InputEvent::AxisMotion(id, _, _) => Some(self.player(id)?),
+            _ => None,
+        };
+        Some((player, event))
+    }
+}
+
+fn translate_button(button: controller::Button) -> Button {
+    match button {
+        controller::Button::A => Button::A,
+        controller::Button::B => Button::B,
+        controller::Button::X => Button::X,
+        controller::Button::Y => Button::Y,
+        controller::Button::Back => Button::Back,
+        controller::Button::Guide => Button::Guide,
+        controller::Button::Start => Button::Start,
+        controller::Button::LeftStick => Button::LeftStick,
+        controller::Button::RightStick => Button::RightStick,
+        controller::Button::LeftShoulder => Button::LeftShoulder,
+        controller::Button::RightShoulder => Button::RightShoulder,
+        controller::Button::DPadUp => Button::DPadUp,
+        controller::Button::DPadDown => Button::DPadDown,
+        controller::Button::DPadLeft => Button::DPadLeft,
+        controller::Button::DPadRight => Button::DPadRight,
+    }
+}
+
+fn translate_axis(axis: controller::Axis) -> Axis {
+    match axis {
+        controller::Axis::LeftX => Axis::LeftX,
+        controller::Axis::LeftY => Axis::LeftY,
+        controller::Axis::RightX => Axis::RightX,
+        controller::Axis::RightY => Axis::RightY,
+        controller::Axis::TriggerLeft => Axis::TriggerLeft,
+        controller::Axis::TriggerRight => Axis::TriggerRight,
+    }
+}
+
+// Updates each player's controls, which go with the bindings of the
+// same index. Controllers beyond the number of players are ignored.
+pub fn process_input(
+    event_pump: &mut EventPump,
+    input: &mut SdlInput,
+    controls: &mut [Control],
+    bindings: &[Bindings],
+) -> Result<(), Error> {
+    for event in event_pump.poll_iter() {
+        let (player, event) = match input.translate(event) {
+            Some(translated) => translated,
+            None => continue,
+        };
+        for (i, (control, bindings)) in controls.iter_mut().zip(bindings).enumerate() {
+            if player.is_none_or(|player| player == i) {
+                handle_input(control, bindings, &event);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,1357 @@
+use serde::{Deserialize, Serialize};
+use slotmap::SecondaryMap;
+use std::cmp::max;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use crate::control::Control;
+use crate::direction::Direction;
+use crate::error::Error;
+use crate::event::Event;
+use crate::fixed::Fixed;
+use crate::level::{Level, ENTITY_SIZE};
+use crate::point::Point;
+use crate::rect::Rect;
+use crate::rng::Rng;
+use crate::state::{join3, join_mut, Ai, Components, EntityId, EntityKind, State, Stats};
+use crate::tilemap::TileMap;
+use crate::wave::{Edge, SpawnPoint, Waves};
+
+// How many random positions to try before giving up on a spawn.
+const SPAWN_ATTEMPTS: usize = 8;
+
+// Top speed of the player in pixels per frame, and how much it speeds up
+// or slows down by each frame.
+const PLAYER_SPEED: Fixed = Fixed::from_int(2);
+const PLAYER_ACCELERATION: Fixed = Fixed::from_ratio(1, 2);
+
+// Chasing monsters move a pixel per frame for every this much agro.
+const AGRO_PER_SPEED: i32 = 320;
+
+// The complete simulation: everything needed to advance the game by
+// one frame, independent of any window or input device.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct World {
+    pub state: State,
+    // Every player, in order, including those who have died.
+    pub players: Vec<EntityId>,
+    pub arena: Rect,
+    pub frame_number: u64,
+    // The level the game started from, so it can be started over. Saves
+    // from before this was kept don't have it.
+    #[serde(default)]
+    pub level: Option<Level>,
+
+    // Hitboxes as of the start of the last step, so that rendering can
+    // interpolate between ticks.
+    #[serde(skip)]
+    pub previous_hitboxes: SecondaryMap<EntityId, Rect>,
+}
+
+impl World {
+    pub fn new(level: &Level, seed: u64) -> World {
+        World::with_players(level, seed, 1)
+    }
+
+    // Players after the first start at the level's co-op starts, or
+    // where the first one does once those run out.
+    pub fn with_players(level: &Level, seed: u64, count: usize) -> World {
+        let size = ENTITY_SIZE;
+        let mut state = State::new(seed);
+        state.weapons = level.weapons.clone();
+        state.waves = Waves::new(level.waves.clone());
+        // Levels are validated when loaded, so this only falls back to no
+        // walls for levels built in code.
+        state.tiles = level.tile_map().unwrap_or_default();
+        let mut players = Vec::new();
+        for i in 0..count {
+            let p = match i {
+                0 => level.player,
+                i => level
+                    .co_op_starts
+                    .get(i - 1)
+                    .copied()
+                    .unwrap_or(level.player),
+            };
+            players.push(state.spawn_player(Rect::new_with_size(p.x, p.y, size, size)));
+        }
+        for monster in &level.monsters {
+            let p = monster.position;
+            state.spawn_monster(Rect::new_with_size(p.x, p.y, size, size), monster.facing);
+        }
+        for &p in &level.powers {
+            state.spawn_power(Rect::new_with_size(p.x, p.y, size, size));
+        }
+
+        World {
+            state,
+            players,
+            arena: level.arena(),
+            frame_number: 0,
+            level: Some(level.clone()),
+            previous_hitboxes: SecondaryMap::new(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<World, Error> {
+        let text = fs::read_to_string(path)?;
+        Ok(ron::from_str(&text)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let config = ron::ser::PrettyConfig::new().depth_limit(3);
+        let text = ron::ser::to_string_pretty(self, config)?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    // A hash of everything that changes as the game runs, which is the
+    // same on every machine that's run the same frames, so peers can
+    // compare theirs to tell whether they've drifted apart.
+    pub fn checksum(&self) -> u64 {
+        checksum(&self.state, self.frame_number)
+    }
+
+    // Just the parts of the world that change as the game runs, to go
+    // back to later. Cheaper than a clone, which also copies the walls,
+    // weapons and wave schedule.
+    pub fn snapshot(&self) -> Snapshot {
+        let s = &self.state;
+        Snapshot {
+            state: State {
+                entities: s.entities.clone(),
+                hitbox: s.hitbox.clone(),
+                position: s.position.clone(),
+                velocity: s.velocity.clone(),
+                kind: s.kind.clone(),
+                facing: s.facing.clone(),
+                attack: s.attack.clone(),
+                inventory: s.inventory.clone(),
+                health: s.health.clone(),
+                ai: s.ai.clone(),
+                stats: s.stats.clone(),
+                final_stats: s.final_stats.clone(),
+                rng: s.rng,
+                waves: s.waves.progress(),
+                kills: s.kills,
+                next_power_frame: s.next_power_frame,
+                ..State::default()
+            },
+            frame_number: self.frame_number,
+        }
+    }
+
+    // Goes back to a snapshot taken of this world.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        let (s, from) = (&mut self.state, &snapshot.state);
+        s.entities.clone_from(&from.entities);
+        s.hitbox.clone_from(&from.hitbox);
+        s.position.clone_from(&from.position);
+        s.velocity.clone_from(&from.velocity);
+        s.kind.clone_from(&from.kind);
+        s.facing.clone_from(&from.facing);
+        s.attack.clone_from(&from.attack);
+        s.inventory.clone_from(&from.inventory);
+        s.health.clone_from(&from.health);
+        s.ai.clone_from(&from.ai);
+        s.stats.clone_from(&from.stats);
+        s.final_stats.clone_from(&from.final_stats);
+        s.rng = from.rng;
+        s.waves.restore(&from.waves);
+        s.kills = from.kills;
+        s.next_power_frame = from.next_power_frame;
+        s.events.clear();
+        s.spatial.update(&s.hitbox);
+        self.frame_number = snapshot.frame_number;
+    }
+
+    // The players still in the game.
+    pub fn living_players(&self) -> impl Iterator<Item = EntityId> + '_ {
+        self.players
+            .iter()
+            .copied()
+            .filter(move |&id| self.state.contains(id))
+    }
+
+    // A player's stats, kept after they've died.
+    pub fn stats(&self, player: EntityId) -> Option<Stats> {
+        let state = &self.state;
+        state
+            .stats
+            .get(player)
+            .or_else(|| state.final_stats.get(player))
+            .copied()
+    }
+
+    // Everyone's score, the dead included.
+    pub fn score(&self) -> i32 {
+        self.players
+            .iter()
+            .filter_map(|&id| self.stats(id))
+            .map(|stats| stats.score)
+            .sum()
+    }
+
+    // The game ends once every player has died.
+    pub fn is_over(&self) -> bool {
+        self.living_players().next().is_none()
+    }
+
+    // Takes a control for each player, in order. Players without one
+    // stand still.
+    pub fn step(&mut self, controls: &[Control]) {
+        self.previous_hitboxes = self.state.hitbox.clone();
+        self.state.events.clear();
+
+        // Note: start by processing collisions first. This is in effect
+        // capturing the collisions from the *last frame*, which is important
+        // so that you can actually see something you hit instead of
+        // dissappearing off the screen before you even see it.
+        process_collisions(&mut self.state, &self.players);
+        process_scripts(
+            &mut self.state,
+            &self.players,
+            self.arena,
+            self.frame_number,
+        );
+        let idle = Control::default();
+        for (i, &player) in self.players.iter().enumerate() {
+            let control = controls.get(i).unwrap_or(&idle);
+            process_action(&mut self.state, player, control, self.arena);
+        }
+        self.frame_number += 1;
+    }
+
+    // Where to draw an entity when rendering alpha (0..1) of the way
+    // from the previous step to the current one.
+    pub fn interpolated_hitbox(&self, id: EntityId, alpha: f32) -> Option<Rect> {
+        let hitbox = *self.state.hitbox.get(id)?;
+        Some(match self.previous_hitboxes.get(id) {
+            Some(previous) => previous.lerp(hitbox, alpha),
+            None => hitbox,
+        })
+    }
+}
+
+// A World as of some frame, from World::snapshot. Only the parts of the
+// state that change are filled in.
+#[derive(Clone)]
+pub struct Snapshot {
+    state: State,
+    frame_number: u64,
+}
+
+impl Snapshot {
+    // The checksum of the world this was taken of, as it was then.
+    pub fn checksum(&self) -> u64 {
+        checksum(&self.state, self.frame_number)
+    }
+}
+
+// FNV-1a, which unlike the standard library's hasher is fixed. Numbers
+// go in little-endian, and sizes as 64 bits, so the result is the same
+// on every machine.
+struct Fnv(u64);
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+fn hash_components<T: Hash>(components: &Components<T>, hasher: &mut Fnv) {
+    components.len().hash(hasher);
+    for (id, component) in components {
+        id.hash(hasher);
+        component.hash(hasher);
+    }
+}
+
+fn checksum(state: &State, frame_number: u64) -> u64 {
+    let mut hasher = Fnv(0xcbf2_9ce4_8422_2325);
+    let h = &mut hasher;
+    state.entities.len().hash(h);
+    for id in state.entities.keys() {
+        id.hash(h);
+    }
+    hash_components(&state.hitbox, h);
+    hash_components(&state.position, h);
+    hash_components(&state.velocity, h);
+    hash_components(&state.kind, h);
+    hash_components(&state.facing, h);
+    hash_components(&state.attack, h);
+    hash_components(&state.inventory, h);
+    hash_components(&state.health, h);
+    hash_components(&state.ai, h);
+    hash_components(&state.stats, h);
+    hash_components(&state.final_stats, h);
+    state.rng.hash(h);
+    state.waves.hash_progress(h);
+    state.kills.hash(h);
+    state.next_power_frame.hash(h);
+    frame_number.hash(h);
+    hasher.finish()
+}
+
+pub fn process_scripts(state: &mut State, players: &[EntityId], window: Rect, frame_number: u64) {
+    let player_hitboxes: Vec<Rect> = players
+        .iter()
+        .filter_map(|&id| state.hitbox.get(id).copied())
+        .collect();
+    // Every monster decides where to head first, and then they all move,
+    // turning left when they hit an object or a wall.
+    let steering: Vec<_> = join3(&state.ai, &state.hitbox, &state.facing)
+        .map(|(id, ai, &hitbox, &dir)| (id, dir, steer(ai, hitbox, dir, &player_hitboxes)))
+        .collect();
+    for (id, dir, (velocity, facing)) in steering {
+        let (hitbox, position) = match (state.hitbox.get_mut(id), state.position.get_mut(id)) {
+            (Some(hitbox), Some(position)) => (hitbox, position),
+            _ => continue,
+        };
+        let moved = move_entity(&state.tiles, window, hitbox, position, velocity);
+        state.velocity.insert(id, velocity);
+        state
+            .facing
+            .insert(id, if moved { facing } else { dir.turn_left() });
+    }
+    for (_, ai, _) in join_mut(&mut state.ai, &state.hitbox) {
+        ai.agro += 1;
+    }
+
+    // Monsters spawn just inside the arena, as scheduled by the waves,
+    // and power-ups at random positions and random intervals. Either one
+    // is skipped if we can't find a spot clear of walls.
+    let spawn = window.grow(-32);
+
+    for wave_spawn in state
+        .waves
+        .update(frame_number, state.kills, &mut state.rng)
+    {
+        let (rng, tiles) = (&mut state.rng, &state.tiles);
+        let lo = match find_spawn(tiles, || spawn_position(wave_spawn.point, spawn, rng)) {
+            Some(lo) => lo,
+            None => continue,
+        };
+        let facing = Direction::ALL[state.rng.range(0, 8) as usize];
+        let id = state.spawn_monster(Rect::new_with_size(lo.x, lo.y, 32, 32), facing);
+        state.ai[id].agro = wave_spawn.monster.initial_agro();
+        state.health[id] = wave_spawn.monster.health();
+        state.events.emit(Event::EntitySpawned {
+            id,
+            kind: EntityKind::Monster,
+        });
+    }
+
+    if frame_number >= state.next_power_frame {
+        let (rng, tiles) = (&mut state.rng, &state.tiles);
+        let lo = find_spawn(tiles, || {
+            let x = rng.range(spawn.lo.x, spawn.hi.x + 1);
+            let y = rng.range(spawn.lo.y, spawn.hi.y + 1);
+            Point::new(x, y)
+        });
+        state.next_power_frame = frame_number + state.rng.range(125, 225) as u64;
+        if let Some(lo) = lo {
+            let id = state.spawn_power(Rect::new_with_size(lo.x, lo.y, 32, 32));
+            state.events.emit(Event::EntitySpawned {
+                id,
+                kind: EntityKind::Power,
+            });
+        }
+    }
+}
+
+// A monster's velocity, and the way it faces: straight ahead until it
+// gets agitated enough, and then after the closest player.
+fn steer(ai: &Ai, hitbox: Rect, facing: Direction, players: &[Rect]) -> (Point<Fixed>, Direction) {
+    if ai.agro < 240 {
+        return (facing.to_point().into(), facing);
+    }
+
+    // Chase whichever player is closest, the first of them on a tie.
+    let center = hitbox.center();
+    let player_hitbox = match players.iter().min_by_key(|hitbox| {
+        let d = hitbox.center() - center;
+        d.x * d.x + d.y * d.y
+    }) {
+        Some(player_hitbox) => player_hitbox,
+        None => return (Point::default(), facing),
+    };
+
+    // Head for the player diagonally, unless they're mostly along one
+    // axis, faster the longer we've been chasing.
+    let speed = Fixed::from_ratio(ai.agro, AGRO_PER_SPEED);
+    let target = player_hitbox.center() - center;
+    let step = Point::new(
+        if target.x.abs() * 2 > target.y.abs() {
+            target.x.signum()
+        } else {
+            0
+        },
+        if target.y.abs() * 2 > target.x.abs() {
+            target.y.signum()
+        } else {
+            0
+        },
+    );
+    let facing = Direction::from_point(step).unwrap_or(facing);
+    (Point::<Fixed>::from(step) * speed, facing)
+}
+
+// Moves an entity by its velocity, keeping track of where it is to a
+// fraction of a pixel. It stops at the edge of the window and at walls,
+// dropping the fraction on whichever axis was blocked. Returns whether
+// it got as far as it wanted along any axis it was moving on.
+fn move_entity(
+    tiles: &TileMap,
+    window: Rect,
+    hitbox: &mut Rect,
+    position: &mut Point<Fixed>,
+    velocity: Point<Fixed>,
+) -> bool {
+    // Anything that moves the hitbox directly, rather than through the
+    // velocity, puts the entity on that pixel.
+    if position.floor() != hitbox.lo {
+        *position = hitbox.lo.into();
+    }
+
+    let target = *position + velocity;
+    let lo = window.grow(-hitbox.size().x).clamp(target.floor());
+    *hitbox = tiles.move_rect(*hitbox, lo - hitbox.lo);
+
+    let free_x = hitbox.lo.x == target.x.floor();
+    let free_y = hitbox.lo.y == target.y.floor();
+    *position = Point::new(
+        if free_x { target.x } else { hitbox.lo.x.into() },
+        if free_y { target.y } else { hitbox.lo.y.into() },
+    );
+    (free_x && velocity.x != Fixed::ZERO) || (free_y && velocity.y != Fixed::ZERO)
+}
+
+fn find_spawn(tiles: &TileMap, mut position: impl FnMut() -> Point) -> Option<Point> {
+    (0..SPAWN_ATTEMPTS)
+        .map(|_| position())
+        .find(|p| !tiles.is_blocked(Rect::new_with_size(p.x, p.y, 32, 32)))
+}
+
+fn spawn_position(point: SpawnPoint, spawn: Rect, rng: &mut Rng) -> Point {
+    let edge = match point {
+        SpawnPoint::At(p) => return p,
+        SpawnPoint::Edge(edge) => edge,
+        SpawnPoint::AnyEdge => {
+            [Edge::Top, Edge::Left, Edge::Bottom, Edge::Right][rng.range(0, 4) as usize]
+        }
+    };
+    match edge {
+        Edge::Top => Point::new(rng.range(spawn.lo.x, spawn.hi.x + 1), spawn.lo.y),
+        Edge::Left => Point::new(spawn.lo.x, rng.range(spawn.lo.y, spawn.hi.y + 1)),
+        Edge::Bottom => Point::new(rng.range(spawn.lo.x, spawn.hi.x + 1), spawn.hi.y),
+        Edge::Right => Point::new(spawn.hi.x, rng.range(spawn.lo.y, spawn.hi.y + 1)),
+    }
+}
+
+pub fn process_action(state: &mut State, player_id: EntityId, control: &Control, window: Rect) {
+    let (hitbox, position, velocity, facing, attack, inventory, stats) = match (
+        state.hitbox.get_mut(player_id),
+        state.position.get_mut(player_id),
+        state.velocity.get_mut(player_id),
+        state.facing.get_mut(player_id),
+        state.attack.get_mut(player_id),
+        state.inventory.get_mut(player_id),
+        state.stats.get(player_id),
+    ) {
+        (
+            Some(hitbox),
+            Some(position),
+            Some(velocity),
+            Some(facing),
+            Some(attack),
+            Some(inventory),
+            Some(stats),
+        ) => (hitbox, position, velocity, facing, attack, inventory, stats),
+        _ => return,
+    };
+
+    // Speed up towards the direction held, or slow down to a stop.
+    let target = Point::<Fixed>::from(Point::new(control.left_right_input, control.up_down_input))
+        * PLAYER_SPEED;
+    *velocity = Point::new(
+        velocity.x.approach(target.x, PLAYER_ACCELERATION),
+        velocity.y.approach(target.y, PLAYER_ACCELERATION),
+    );
+    move_entity(&state.tiles, window, hitbox, position, *velocity);
+
+    *facing = control.facing_input;
+
+    // Weapons can only be switched between swings.
+    let count = inventory.weapons.len() as i32;
+    if control.switch_weapon_input != 0 && attack.frame.is_none() && count > 1 {
+        let current = inventory.current as i32 + control.switch_weapon_input;
+        inventory.current = current.rem_euclid(count) as usize;
+        state.events.emit(Event::WeaponSwitched {
+            player: player_id,
+            weapon: inventory.weapons[inventory.current],
+        });
+    }
+
+    let weapons = &state.weapons;
+    let weapon = match inventory
+        .weapons
+        .get(inventory.current)
+        .and_then(|&i| weapons.get(i))
+    {
+        Some(weapon) => weapon,
+        None => {
+            attack.frame = None;
+            attack.hitbox = Rect::default();
+            return;
+        }
+    };
+
+    attack.frame = match attack.frame {
+        Some(tick) if tick + 1 < weapon.duration => Some(tick + 1),
+        Some(_) => {
+            attack.cooldown = weapon.cooldown;
+            None
+        }
+        None if attack.cooldown > 0 => {
+            attack.cooldown -= 1;
+            None
+        }
+        None if control.attack_input => {
+            attack.hit.clear();
+            Some(0)
+        }
+        None => None,
+    };
+
+    attack.hitbox = match attack.frame {
+        Some(tick) => {
+            let b = *hitbox;
+            let top_center = b.index(0, -1);
+            (weapon.hitbox(tick, stats.power) + top_center).rotate(b.center(), facing.angle())
+        }
+        None => Rect::default(),
+    };
+}
+
+// Collisions are gathered first and then resolved together, so that
+// everything overlapping on a frame counts on that frame, in this order:
+//
+//   1. Each player's attack hits every monster it touches, once per
+//      swing, killing those it takes the last of their health from.
+//   2. Each player collects every power-up it touches.
+//   3. Each player dies if any monster still alive touches it.
+//
+// The first two happen for one player after another, so if two players
+// reach the same monster or power-up on the same frame, the one that
+// comes first gets it. A monster cut down on the same frame it reaches
+// a player doesn't kill them, and kills and pickups still count on the
+// frame they die.
+pub fn process_collisions(state: &mut State, players: &[EntityId]) {
+    state.spatial.update(&state.hitbox);
+
+    for &player in players {
+        process_player_collisions(state, player);
+    }
+
+    let killers: Vec<_> = players
+        .iter()
+        .filter_map(|&player| Some((player, find_killer(state, player)?)))
+        .collect();
+    for (player, monster) in killers {
+        if let Some(&stats) = state.stats.get(player) {
+            state.final_stats.insert(player, stats);
+        }
+        state.despawn(player);
+        state.events.emit(Event::PlayerDied { player, monster });
+    }
+}
+
+// Attacks and pickups for one player.
+fn process_player_collisions(state: &mut State, player_id: EntityId) {
+    let player_hitbox = match state.hitbox.get(player_id) {
+        Some(&hitbox) => hitbox,
+        None => return,
+    };
+    let player_attack = state
+        .attack
+        .get(player_id)
+        .map(|a| a.hitbox)
+        .unwrap_or_default();
+    let damage = state.weapon(player_id).map_or(0, |w| w.damage);
+
+    // Only what's touching the player or its attack can collide. Sorted,
+    // so everything below happens in id order.
+    let mut candidates = state.spatial.query_rect(player_hitbox);
+    candidates.extend(state.spatial.query_rect(player_attack));
+    candidates.sort();
+    candidates.dedup();
+
+    let mut hits = Vec::new();
+    let mut killed = Vec::new();
+    let mut collected = Vec::new();
+    for id in candidates {
+        let (kind, hitbox) = match (state.kind.get(id), state.hitbox.get(id)) {
+            (Some(&kind), Some(&hitbox)) => (kind, hitbox),
+            _ => continue,
+        };
+        match kind {
+            EntityKind::Monster => {
+                let hit_before = state
+                    .attack
+                    .get(player_id)
+                    .is_some_and(|a| a.hit.contains(&id));
+                if player_attack.has_intersection(hitbox) && !hit_before {
+                    hits.push(id);
+                    if state.health.get(id).map_or(0, |&h| h - damage) <= 0 {
+                        killed.push(id);
+                    }
+                }
+            }
+            EntityKind::Power => {
+                if player_hitbox.has_intersection(hitbox) {
+                    collected.push(id);
+                }
+            }
+            EntityKind::Player => {}
+        }
+    }
+
+    if let Some(attack) = state.attack.get_mut(player_id) {
+        attack.hit.extend(&hits);
+    }
+    for &monster in &hits {
+        if let Some(health) = state.health.get_mut(monster) {
+            *health -= damage;
+        }
+        if !killed.contains(&monster) {
+            state.events.emit(Event::MonsterHit {
+                monster,
+                player: player_id,
+            });
+        }
+    }
+    for &monster in &killed {
+        state.despawn(monster);
+        state.events.emit(Event::MonsterKilled {
+            monster,
+            player: player_id,
+        });
+    }
+    for &power in &collected {
+        state.despawn(power);
+        state.events.emit(Event::PowerCollected {
+            power,
+            player: player_id,
+        });
+    }
+
+    // Each kill costs a point of power, and each power-up gives four,
+    // all at once, so power only bottoms out at zero if the frame's
+    // kills outweigh its pickups.
+    let kills = killed.len() as i32;
+    state.kills += kills as u32;
+    if let Some(stats) = state.stats.get_mut(player_id) {
+        stats.score += kills;
+        stats.power = max(stats.power - kills + 4 * collected.len() as i32, 0);
+    }
+}
+
+// The first monster still alive that touches the player, or whose
+// attack does.
+fn find_killer(state: &State, player_id: EntityId) -> Option<EntityId> {
+    let player_hitbox = *state.hitbox.get(player_id)?;
+    let mut candidates = state.spatial.query_rect(player_hitbox);
+    candidates.extend(state.attack.keys().filter(|&id| id != player_id));
+    candidates.sort();
+    candidates.dedup();
+    candidates.into_iter().find(|&id| {
+        let hitbox = match (state.kind.get(id), state.hitbox.get(id)) {
+            (Some(EntityKind::Monster), Some(&hitbox)) => hitbox,
+            _ => return false,
+        };
+        let attack = state.attack.get(id).map(|a| a.hitbox).unwrap_or_default();
+        hitbox.has_intersection(player_hitbox) || attack.has_intersection(player_hitbox)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_same(s1: &State, s2: &State) {
+        let ids1: Vec<_> = s1.entities.keys().collect();
+        let ids2: Vec<_> = s2.entities.keys().collect();
+        assert_eq!(ids1, ids2);
+        assert!(s1.hitbox == s2.hitbox);
+        assert!(s1.position == s2.position);
+        assert!(s1.velocity == s2.velocity);
+        assert!(s1.kind == s2.kind);
+        assert!(s1.facing == s2.facing);
+        assert!(s1.attack == s2.attack);
+        assert!(s1.inventory == s2.inventory);
+        assert!(s1.health == s2.health);
+        assert!(s1.ai == s2.ai);
+        assert!(s1.stats == s2.stats);
+        assert_eq!(s1.rng, s2.rng);
+    }
+
+    #[test]
+    fn world_step() {
+        let mut world = World::new(&Level::default(), 0);
+        assert_eq!(world.state.entities.len(), 5);
+
+        world.step(&[Control::default()]);
+        assert_eq!(world.frame_number, 1);
+        assert_eq!(world.state.entities.len(), 6); // Monster spawned on frame 0.
+
+        let events: Vec<_> = world.state.events.iter().collect();
+        assert_eq!(events.len(), 1);
+        match events[0] {
+            Event::EntitySpawned { id, kind } => {
+                assert_eq!(*kind, EntityKind::Monster);
+                assert!(world.state.ai.contains_key(*id));
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        world.step(&[Control::default()]);
+        assert!(world.state.events.is_empty());
+    }
+
+    #[test]
+    fn world_player_moves() {
+        let mut world = World::new(&Level::default(), 0);
+        let start = world.state.hitbox[world.players[0]];
+
+        let mut control = Control::default();
+        control.left_right_input = 1;
+        control.facing_input = Direction::Right;
+        world.step(&[control.clone()]);
+        assert_eq!(world.state.facing[world.players[0]], Direction::Right);
+
+        // Speeds up by half a pixel per frame up to two, moving a
+        // fraction of a pixel at first.
+        let player = world.players[0];
+        let mut xs = Vec::new();
+        for _ in 0..5 {
+            xs.push(world.state.hitbox[player].lo.x - start.lo.x);
+            world.step(&[control.clone()]);
+        }
+        assert_eq!(xs, vec![0, 1, 3, 5, 7]);
+        assert_eq!(
+            world.state.position[player].x,
+            Fixed::from_int(start.lo.x + 9)
+        );
+        assert_eq!(
+            world.state.velocity[player],
+            Point::new(PLAYER_SPEED, Fixed::ZERO)
+        );
+
+        // And slows down again when let go.
+        world.step(&[Control::default()]);
+        assert_eq!(world.state.velocity[player].x, Fixed::from_ratio(3, 2));
+        for _ in 0..3 {
+            world.step(&[Control::default()]);
+        }
+        assert_eq!(world.state.velocity[player], Point::default());
+        assert_eq!(world.state.hitbox[player].lo.x - start.lo.x, 12);
+        assert_eq!(world.state.hitbox[player].lo.y, start.lo.y);
+    }
+
+    #[test]
+    fn world_monster_speed() {
+        let mut level = Level::default();
+        level.waves.clear();
+        level.monsters.truncate(1);
+        let mut world = World::new(&level, 0);
+        let monster = world.state.ai.keys().next().unwrap();
+
+        // Chasing speed grows smoothly with agro rather than in whole
+        // pixels, and moving slower than a pixel per frame still gets
+        // there.
+        world.state.ai[monster].agro = 400;
+        world.step(&[Control::default()]);
+        let speed = world.state.velocity[monster].x.abs() + world.state.velocity[monster].y.abs();
+        assert!(speed >= Fixed::from_ratio(400, 320));
+        assert!(speed <= Fixed::from_ratio(400, 320) * 2);
+
+        world.state.ai[monster].agro = 240;
+        world.state.velocity[monster] = Point::default();
+        let start = world.state.hitbox[monster];
+        for _ in 0..4 {
+            world.step(&[Control::default()]);
+        }
+        let moved = world.state.hitbox[monster].lo - start.lo;
+        assert!(moved.x.abs() + moved.y.abs() >= 3);
+    }
+
+    #[test]
+    fn world_player_dies() {
+        let mut world = World::new(&Level::default(), 0);
+        let hitbox = world.state.hitbox[world.players[0]];
+        let monster = world.state.ai.keys().next().unwrap();
+        world.state.hitbox[monster] = hitbox;
+
+        world.step(&[Control::default()]);
+        assert!(!world.state.contains(world.players[0]));
+        assert!(world.state.hitbox.get(world.players[0]).is_none());
+        assert!(world.state.events.iter().any(|e| *e
+            == Event::PlayerDied {
+                player: world.players[0],
+                monster
+            }));
+    }
+
+    #[test]
+    fn world_monster_killed() {
+        let mut world = World::new(&Level::default(), 0);
+        let player = world.players[0];
+        let monster = world.state.ai.keys().next().unwrap();
+        world.state.hitbox[monster] = world.state.hitbox[player] + Point::new(0, -40);
+        world.state.attack[player].hitbox = world.state.hitbox[monster];
+        world.state.stats[player].power = 3;
+
+        world.step(&[Control::default()]);
+        assert!(!world.state.contains(monster));
+        assert_eq!(world.state.stats[player], Stats { score: 1, power: 2 });
+        assert!(world
+            .state
+            .events
+            .iter()
+            .any(|e| *e == Event::MonsterKilled { monster, player }));
+    }
+
+    fn co_op_level() -> Level {
+        let mut level = Level::default();
+        level.monsters.clear();
+        level.waves.clear();
+        level
+    }
+
+    #[test]
+    fn world_co_op_stats() {
+        let mut world = World::with_players(&co_op_level(), 0, 3);
+        let players = world.players.clone();
+        assert_eq!(world.state.hitbox[players[1]].lo, Point::new(360, 300));
+        assert_eq!(
+            world.state.hitbox[players[2]],
+            world.state.hitbox[players[0]]
+        );
+
+        // The second player kills a monster, while the first and third
+        // reach the same power-up, which goes to the first.
+        let hitbox = world.state.hitbox[players[1]] + Point::new(0, -40);
+        let monster = world.state.spawn_monster(hitbox, Direction::Up);
+        world.state.attack[players[1]].hitbox = hitbox;
+        world.state.spawn_power(world.state.hitbox[players[0]]);
+
+        // The third player has no control, and stands still.
+        world.step(&[Control::default(), Control::default()]);
+        assert!(!world.state.contains(monster));
+        assert_eq!(world.state.stats[players[0]], Stats { score: 0, power: 4 });
+        assert_eq!(world.state.stats[players[1]], Stats { score: 1, power: 0 });
+        assert_eq!(world.state.stats[players[2]], Stats::default());
+    }
+
+    #[test]
+    fn world_co_op_chase() {
+        let mut level = co_op_level();
+        level.player = Point::new(100, 100);
+        level.co_op_starts = vec![Point::new(600, 400)];
+        let mut world = World::with_players(&level, 0, 2);
+        let monster = world
+            .state
+            .spawn_monster(Rect::new_with_size(500, 400, 32, 32), Direction::Up);
+        world.state.ai[monster].agro = 400;
+
+        // Heads for the nearest player, then the other once that one's
+        // gone.
+        world.step(&[]);
+        assert_eq!(world.state.facing[monster], Direction::Right);
+        world.state.despawn(world.players[1]);
+        world.step(&[]);
+        assert_eq!(world.state.facing[monster], Direction::UpLeft);
+    }
+
+    #[test]
+    fn world_co_op_game_over() {
+        let mut world = World::with_players(&co_op_level(), 0, 2);
+        let (first, second) = (world.players[0], world.players[1]);
+        let monster = world
+            .state
+            .spawn_monster(world.state.hitbox[first], Direction::Up);
+
+        // The game goes on as long as anyone's alive.
+        world.step(&[]);
+        assert!(!world.state.contains(first));
+        assert_eq!(world.living_players().collect::<Vec<_>>(), vec![second]);
+        assert!(!world.is_over());
+
+        world.state.stats[second].score = 3;
+        world.state.hitbox[monster] = world.state.hitbox[second];
+        world.step(&[]);
+        assert!(!world.state.contains(second));
+        assert!(world.is_over());
+        assert_eq!(world.stats(second), Some(Stats { score: 3, power: 0 }));
+        assert_eq!(world.score(), 3);
+        assert_eq!(world.players, vec![first, second]);
+    }
+
+    #[test]
+    fn world_waves() {
+        let mut level = Level::default();
+        level.monsters.clear();
+        level.waves = crate::wave::parse_waves(
+            "[
+                (trigger: Frame(2), count: 2, spawn: At((x: 100, y: 100)), delay: 3),
+                (trigger: Kills(1), spawn: Edge(Top), monster: Chaser),
+            ]",
+        )
+        .unwrap();
+        let mut world = World::new(&level, 0);
+
+        let mut spawned = Vec::new();
+        for _ in 0..10 {
+            world.step(&[Control::default()]);
+            for event in world.state.events.iter() {
+                if let Event::EntitySpawned { id, .. } = *event {
+                    spawned.push((world.frame_number - 1, id));
+                }
+            }
+        }
+        let frames: Vec<_> = spawned.iter().map(|&(frame, _)| frame).collect();
+        assert_eq!(frames, vec![2, 5]);
+        assert_eq!(world.state.ai[spawned[0].1].agro, 7);
+
+        // Killing a monster triggers the next wave, at the top edge.
+        world.state.despawn(spawned[0].1);
+        world.state.kills = 1;
+        world.step(&[Control::default()]);
+        let id = match world.state.events.iter().next() {
+            Some(&Event::EntitySpawned { id, .. }) => id,
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert_eq!(world.state.hitbox[id].lo.y, world.arena.grow(-32).lo.y);
+        assert_eq!(world.state.ai[id].agro, 320);
+    }
+
+    #[test]
+    fn world_walls() {
+        let mut level = Level::default();
+        level.monsters.truncate(1);
+        level.waves.clear();
+        // A wall just right of the player, and one right above the monster.
+        let mut tiles = vec![String::new(); 11];
+        for row in &mut tiles[3..6] {
+            *row = ".........#".to_string();
+        }
+        tiles[10] = "...#".to_string();
+        level.tiles = tiles;
+        level.player = Point::new(256, 100);
+        level.monsters[0].position = Point::new(100, 352);
+        level.validate().unwrap();
+        let mut world = World::new(&level, 0);
+        let player = world.players[0];
+        let monster = world.state.ai.keys().next().unwrap();
+        assert_eq!(world.state.facing[monster], Direction::Up);
+
+        let mut control = Control::default();
+        control.left_right_input = 1;
+        control.up_down_input = 1;
+        for _ in 0..20 {
+            world.step(&[control.clone()]);
+        }
+
+        // The player slid down along the wall.
+        let hitbox = world.state.hitbox[player];
+        assert_eq!(hitbox.hi.x, 9 * 32);
+        assert_eq!(hitbox.lo.y, 100 + 37);
+
+        // The monster walked up into the wall and turned left.
+        assert_eq!(world.state.hitbox[monster].lo.y, 11 * 32);
+        assert!(world.state.hitbox[monster].lo.x < 100);
+        assert_eq!(world.state.facing[monster], Direction::Left);
+    }
+
+    #[test]
+    fn world_diagonal_attack() {
+        let mut level = Level::default();
+        level.monsters.clear();
+        level.waves.clear();
+        let mut world = World::new(&level, 0);
+        let player = world.players[0];
+
+        let mut control = Control::default();
+        control.facing_input = Direction::UpRight;
+        control.attack_input = true;
+        world.step(&[control.clone()]);
+
+        // Turned with integer math, so it's exactly this everywhere.
+        let attack = &world.state.attack[player];
+        assert_eq!(attack.frame, Some(0));
+        assert_eq!(
+            attack.hitbox,
+            Rect::new((436, 309).into(), (440, 313).into())
+        );
+
+        control.attack_input = false;
+        for _ in 0..4 {
+            world.step(&[control.clone()]);
+        }
+        let attack = &world.state.attack[player];
+        assert_eq!(attack.frame, Some(4));
+        assert_eq!(
+            attack.hitbox,
+            Rect::new((431, 297).into(), (439, 305).into())
+        );
+    }
+
+    #[test]
+    fn world_weapons() {
+        let mut level = Level::default();
+        level.monsters.clear();
+        level.waves.clear();
+        let mut world = World::new(&level, 0);
+        let player = world.players[0];
+        assert_eq!(world.state.weapon(player).unwrap().name, "sword");
+
+        let mut control = Control::default();
+        control.switch_weapon_input = -1;
+        world.step(&[control.clone()]);
+        assert_eq!(world.state.weapon(player).unwrap().name, "hammer");
+        world.step(&[control.clone()]);
+        let spear = 1;
+        assert_eq!(world.state.weapon(player).unwrap().name, "spear");
+        assert!(world.state.events.iter().any(|e| *e
+            == Event::WeaponSwitched {
+                player,
+                weapon: spear
+            }));
+
+        // A monster that takes two thrusts, standing still in reach.
+        let hitbox = world.state.hitbox[player];
+        let monster = world
+            .state
+            .spawn_monster(hitbox + Point::new(0, -50), Direction::Up);
+        world.state.ai.remove(monster);
+        world.state.health[monster] = 3;
+
+        // Holding attack swings as often as the cooldown allows, and
+        // switching mid-swing does nothing.
+        control.attack_input = true;
+        let mut swing_starts = Vec::new();
+        let mut hits = 0;
+        for tick in 0..40 {
+            control.switch_weapon_input = if (1..10).contains(&tick) { 1 } else { 0 };
+            world.step(&[control.clone()]);
+            if world.state.attack[player].frame == Some(0) {
+                swing_starts.push(tick);
+            }
+            for event in world.state.events.iter() {
+                match *event {
+                    Event::MonsterHit { .. } => hits += 1,
+                    Event::WeaponSwitched { .. } => panic!("switched mid-swing"),
+                    _ => {}
+                }
+            }
+            if tick == 27 {
+                assert_eq!(hits, 1);
+                assert_eq!(world.state.health[monster], 1);
+            }
+        }
+        let cooldown = world.state.weapons[spear].cooldown as usize;
+        let duration = world.state.weapons[spear].duration;
+        assert_eq!(swing_starts, vec![0, duration + cooldown + 1]);
+        assert!(!world.state.contains(monster));
+        assert_eq!(world.state.stats[player].score, 1);
+    }
+
+    #[test]
+    fn world_simultaneous_collisions() {
+        let mut level = Level::default();
+        level.monsters.clear();
+        level.waves.clear();
+        let mut world = World::new(&level, 0);
+        let player = world.players[0];
+        let hitbox = world.state.hitbox[player];
+        let state = &mut world.state;
+
+        // One sweep through three monsters, the last of them touching
+        // the player, while standing on two power-ups.
+        let monsters: Vec<_> = (0..3)
+            .map(|i| {
+                state.spawn_monster(
+                    hitbox + Point::new(-20 + 20 * i, -30 + 10 * i),
+                    Direction::Up,
+                )
+            })
+            .collect();
+        let sweep = Rect::new(
+            hitbox.lo - Point::new(20, 30),
+            hitbox.hi + Point::new(20, -10),
+        );
+        state.attack[player].hitbox = sweep;
+        let powers: Vec<_> = (0..2)
+            .map(|i| state.spawn_power(hitbox + Point::new(4 * i, 4)))
+            .collect();
+        state.stats[player].power = 1;
+
+        world.step(&[Control::default()]);
+        let state = &world.state;
+        assert!(state.contains(player));
+        assert!(monsters.iter().all(|&id| !state.contains(id)));
+        assert!(powers.iter().all(|&id| !state.contains(id)));
+        // Power goes from 1 down 3 for the kills and up 8 for the pickups.
+        assert_eq!(state.stats[player], Stats { score: 3, power: 6 });
+        assert_eq!(state.kills, 3);
+        let events: Vec<_> = state.events.iter().copied().collect();
+        assert_eq!(
+            events,
+            vec![
+                Event::MonsterKilled {
+                    monster: monsters[0],
+                    player
+                },
+                Event::MonsterKilled {
+                    monster: monsters[1],
+                    player
+                },
+                Event::MonsterKilled {
+                    monster: monsters[2],
+                    player
+                },
+                Event::PowerCollected {
+                    power: powers[0],
+                    player
+                },
+                Event::PowerCollected {
+                    power: powers[1],
+                    player
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn world_player_dies_and_kills() {
+        let mut world = World::new(&Level::default(), 0);
+        let player = world.players[0];
+        let hitbox = world.state.hitbox[player];
+        let state = &mut world.state;
+        let ids: Vec<_> = state.ai.keys().collect();
+        for id in ids {
+            state.despawn(id);
+        }
+
+        let killed = state.spawn_monster(hitbox + Point::new(0, -20), Direction::Up);
+        let killer = state.spawn_monster(hitbox + Point::new(0, 20), Direction::Up);
+        state.attack[player].hitbox = state.hitbox[killed];
+
+        world.step(&[Control::default()]);
+        let state = &world.state;
+        assert!(!state.contains(player));
+        assert!(!state.contains(killed));
+        assert!(state.contains(killer));
+        assert_eq!(state.kills, 1);
+        let events: Vec<_> = state.events.iter().copied().collect();
+        assert_eq!(
+            events[0],
+            Event::MonsterKilled {
+                monster: killed,
+                player
+            }
+        );
+        assert_eq!(
+            events[1],
+            Event::PlayerDied {
+                player,
+                monster: killer
+            }
+        );
+
+        // The kill still counts towards the final score.
+        assert_eq!(world.stats(player), Some(Stats { score: 1, power: 0 }));
+        assert_eq!(world.score(), 1);
+    }
+
+    #[test]
+    fn world_crowded() {
+        let mut world = World::new(&Level::default(), 0);
+        let player = world.players[0];
+        for i in 0..3000 {
+            let (x, y) = (i % 60 * 12, i / 60 * 4);
+            world
+                .state
+                .spawn_monster(Rect::new_with_size(x, y, 8, 8), Direction::Left);
+        }
+        let hitbox = world.state.hitbox[player];
+        let power = world.state.spawn_power(hitbox + Point::new(16, 0));
+
+        world.step(&[Control::default()]);
+        assert!(world.state.contains(player));
+        assert!(!world.state.contains(power));
+    }
+
+    #[test]
+    fn world_interpolated_hitbox() {
+        let mut world = World::new(&Level::default(), 0);
+        let mut control = Control::default();
+        control.up_down_input = 1;
+
+        // Get up to full speed first.
+        for _ in 0..3 {
+            world.step(&[control.clone()]);
+        }
+        let start = world.state.hitbox[world.players[0]];
+        world.step(&[control.clone()]);
+
+        let id = world.players[0];
+        assert_eq!(world.interpolated_hitbox(id, 0.0), Some(start));
+        assert_eq!(
+            world.interpolated_hitbox(id, 0.5),
+            Some(start + Point::new(0, 1))
+        );
+        assert_eq!(
+            world.interpolated_hitbox(id, 1.0),
+            Some(start + Point::new(0, 2))
+        );
+    }
+
+    #[test]
+    fn world_save_load() {
+        let mut world = World::new(&Level::default(), 2);
+        let mut control = Control::default();
+        control.attack_input = true;
+        for _ in 0..300 {
+            world.step(&[control.clone()]);
+            control.left_right_input = 1;
+        }
+
+        // Make sure there are vacant slots and bumped versions, so the
+        // round trip has to preserve keys rather than just values.
+        let monster = world.state.ai.keys().next().unwrap();
+        world.state.despawn(monster);
+        world.state.stats[world.players[0]] = Stats { score: 5, power: 2 };
+
+        let path = std::env::temp_dir().join("game_world_save_load.ron");
+        world.save(&path).unwrap();
+        let mut loaded = World::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.players[0], world.players[0]);
+        assert_eq!(loaded.frame_number, world.frame_number);
+        assert_eq!(loaded.arena, world.arena);
+        assert_eq!(loaded.level, Some(Level::default()));
+        assert_eq!(loaded.state.kind[loaded.players[0]], EntityKind::Player);
+        assert_eq!(
+            loaded.state.stats[loaded.players[0]],
+            Stats { score: 5, power: 2 }
+        );
+        assert!(!loaded.state.contains(monster));
+        assert_same(&world.state, &loaded.state);
+
+        // The restored game continues exactly like the original.
+        for _ in 0..300 {
+            world.step(&[control.clone()]);
+            loaded.step(&[control.clone()]);
+        }
+        assert_same(&world.state, &loaded.state);
+
+        // Saves from before players' final stats and the level were kept
+        // still load. Make one by taking those out of a new save.
+        let world = World::new(&Level::default(), 2);
+        let mut text = ron::to_string(&world).unwrap();
+        let level = format!(",level:{}", ron::to_string(&world.level).unwrap());
+        for field in &["final_stats:[(value:None,version:0)],", &level] {
+            assert_eq!(text.matches(field).count(), 1, "{}", field);
+            text = text.replace(field, "");
+        }
+        let old: World = ron::from_str(&text).unwrap();
+        assert!(old.state.final_stats.is_empty());
+        assert_eq!(old.level, None);
+    }
+
+    fn run(seed: u64) -> World {
+        let mut world = World::new(&Level::default(), seed);
+        let mut control = Control::default();
+        for frame in 0..1000 {
+            control.left_right_input = [0, 1, 0, -1][(frame / 50) % 4];
+            control.up_down_input = [1, 0, -1, 0][(frame / 70) % 4];
+            control.attack_input = frame % 20 == 0;
+            world.step(&[control.clone()]);
+        }
+        world
+    }
+
+    #[test]
+    fn world_deterministic() {
+        let w1 = run(42);
+        let w2 = run(42);
+        assert_same(&w1.state, &w2.state);
+
+        let w3 = run(43);
+        assert!(w1.state.hitbox != w3.state.hitbox);
+
+        // Checksums agree exactly when the games do.
+        assert_eq!(w1.checksum(), w2.checksum());
+        assert_ne!(w1.checksum(), w3.checksum());
+    }
+
+    #[test]
+    fn world_snapshot() {
+        // Going back to a snapshot and replaying the same frames ends up
+        // in the same place.
+        let mut world = World::new(&Level::default(), 5);
+        let mut control = Control::default();
+        control.left_right_input = 1;
+        world.step(&[control.clone()]);
+        let snapshot = world.snapshot();
+        let before = world.checksum();
+        assert_eq!(snapshot.checksum(), before);
+        for _ in 0..100 {
+            world.step(&[control.clone()]);
+        }
+        let checksum = world.checksum();
+
+        world.restore(&snapshot);
+        assert_eq!(world.frame_number, 1);
+        assert_eq!(world.checksum(), before);
+        for _ in 0..100 {
+            world.step(&[control.clone()]);
+        }
+        assert_eq!(world.checksum(), checksum);
+        assert_ne!(snapshot.checksum(), checksum);
+
+        // Anything that changes the game changes the checksum.
+        world.state.rng.next_u64();
+        assert_ne!(world.checksum(), checksum);
+    }
+}
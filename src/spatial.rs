@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+
+use crate::point::Point;
+use crate::rect::Rect;
+use crate::state::{Components, EntityId};
+
+// Large enough that most entities only touch a cell or four.
+pub const DEFAULT_CELL_SIZE: i32 = 64;
+
+// Uniform grid over entity hitboxes, so that finding what overlaps a
+// rect only looks at the entities in nearby cells rather than all of
+// them. Results are always sorted by id, so they don't depend on the
+// hash map's iteration order and the simulation stays deterministic.
+#[derive(Clone, Debug)]
+pub struct SpatialHash {
+    cell_size: i32,
+    cells: HashMap<Point, Vec<EntityId>>,
+    hitboxes: Components<Rect>,
+}
+
+impl Default for SpatialHash {
+    fn default() -> SpatialHash {
+        SpatialHash::new(DEFAULT_CELL_SIZE)
+    }
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: i32) -> SpatialHash {
+        assert!(cell_size > 0);
+        SpatialHash {
+            cell_size,
+            cells: HashMap::new(),
+            hitboxes: Components::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.hitboxes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hitboxes.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.hitboxes.clear();
+    }
+
+    // Cells touched by a rect, as an inclusive range.
+    fn cell_range(&self, r: Rect) -> (Point, Point) {
+        let lo = Point::new(
+            r.lo.x.div_euclid(self.cell_size),
+            r.lo.y.div_euclid(self.cell_size),
+        );
+        let hi = Point::new(
+            (r.hi.x - 1).div_euclid(self.cell_size),
+            (r.hi.y - 1).div_euclid(self.cell_size),
+        );
+        (lo, hi)
+    }
+
+    fn cells_of(&self, r: Rect) -> impl Iterator<Item = Point> {
+        let (lo, hi) = self.cell_range(r);
+        (lo.y..=hi.y).flat_map(move |y| (lo.x..=hi.x).map(move |x| Point::new(x, y)))
+    }
+
+    // Adds an entity, or moves it if it's already there. Only touches
+    // the grid if the entity changed cells.
+    pub fn insert(&mut self, id: EntityId, hitbox: Rect) {
+        if let Some(&old) = self.hitboxes.get(id) {
+            if old == hitbox {
+                return;
+            }
+            if !old.is_empty()
+                && !hitbox.is_empty()
+                && self.cell_range(old) == self.cell_range(hitbox)
+            {
+                self.hitboxes.insert(id, hitbox);
+                return;
+            }
+            self.remove(id);
+        }
+
+        self.hitboxes.insert(id, hitbox);
+        // Empty rects can't overlap anything, so they aren't in any cell.
+        if hitbox.is_empty() {
+            return;
+        }
+        for cell in self.cells_of(hitbox) {
+            self.cells.entry(cell).or_default().push(id);
+        }
+    }
+
+    pub fn remove(&mut self, id: EntityId) {
+        let hitbox = match self.hitboxes.remove(id) {
+            Some(hitbox) => hitbox,
+            None => return,
+        };
+        if hitbox.is_empty() {
+            return;
+        }
+        for cell in self.cells_of(hitbox) {
+            if let Some(ids) = self.cells.get_mut(&cell) {
+                ids.retain(|&other| other != id);
+                if ids.is_empty() {
+                    self.cells.remove(&cell);
+                }
+            }
+        }
+    }
+
+    // Brings the index up to date with the given hitboxes, only doing
+    // work for the entities that moved, appeared or disappeared.
+    pub fn update(&mut self, hitboxes: &Components<Rect>) {
+        let gone: Vec<_> = self
+            .hitboxes
+            .keys()
+            .filter(|&id| !hitboxes.contains_key(id))
+            .collect();
+        for id in gone {
+            self.remove(id);
+        }
+        for (id, &hitbox) in hitboxes {
+            self.insert(id, hitbox);
+        }
+    }
+
+    // Entities whose hitbox overlaps r, sorted by id.
+    pub fn query_rect(&self, r: Rect) -> Vec<EntityId> {
+        if r.is_empty() {
+            return Vec::new();
+        }
+        let mut ids: Vec<_> = self
+            .cells_of(r)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .filter(|&id| self.hitboxes[id].has_intersection(r))
+            .collect();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    // Every pair of entities whose hitboxes overlap, each pair once with
+    // the smaller id first, sorted.
+    pub fn pairs(&self) -> Vec<(EntityId, EntityId)> {
+        let mut pairs = Vec::new();
+        for ids in self.cells.values() {
+            for (i, &a) in ids.iter().enumerate() {
+                for &b in &ids[i + 1..] {
+                    if self.hitboxes[a].has_intersection(self.hitboxes[b]) {
+                        pairs.push(if a < b { (a, b) } else { (b, a) });
+                    }
+                }
+            }
+        }
+        pairs.sort();
+        pairs.dedup();
+        pairs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+    use slotmap::SlotMap;
+
+    fn random_hitboxes(count: usize, seed: u64) -> Components<Rect> {
+        let mut rng = Rng::new(seed);
+        let mut ids = SlotMap::new();
+        let mut hitboxes = Components::new();
+        for _ in 0..count {
+            let id: EntityId = ids.insert(());
+            let (x, y) = (rng.range(-100, 2000), rng.range(-100, 2000));
+            let (w, h) = (rng.range(1, 80), rng.range(1, 80));
+            hitboxes.insert(id, Rect::new(Point::new(x, y), Point::new(x + w, y + h)));
+        }
+        hitboxes
+    }
+
+    fn brute_force_pairs(hitboxes: &Components<Rect>) -> Vec<(EntityId, EntityId)> {
+        let mut pairs = Vec::new();
+        for (a, &ra) in hitboxes {
+            for (b, &rb) in hitboxes {
+                if a < b && ra.has_intersection(rb) {
+                    pairs.push((a, b));
+                }
+            }
+        }
+        pairs.sort();
+        pairs
+    }
+
+    #[test]
+    fn spatial_query_rect() {
+        let hitboxes = random_hitboxes(2000, 1);
+        let mut index = SpatialHash::new(32);
+        index.update(&hitboxes);
+        assert_eq!(index.len(), 2000);
+
+        let mut rng = Rng::new(2);
+        for _ in 0..100 {
+            let lo = Point::new(rng.range(-200, 2000), rng.range(-200, 2000));
+            let r = Rect::new(lo, lo + rng.range(1, 300));
+            let expected: Vec<_> = hitboxes
+                .iter()
+                .filter(|(_, h)| h.has_intersection(r))
+                .map(|(id, _)| id)
+                .collect();
+            assert_eq!(index.query_rect(r), expected);
+        }
+    }
+
+    #[test]
+    fn spatial_pairs() {
+        let hitboxes = random_hitboxes(2000, 3);
+        let mut index = SpatialHash::default();
+        index.update(&hitboxes);
+        let pairs = index.pairs();
+        assert!(!pairs.is_empty());
+        assert_eq!(pairs, brute_force_pairs(&hitboxes));
+    }
+
+    #[test]
+    fn spatial_update() {
+        let all = random_hitboxes(600, 4);
+        let ids: Vec<_> = all.keys().collect();
+        let mut hitboxes = all.clone();
+        for &id in &ids[500..] {
+            hitboxes.remove(id);
+        }
+        let mut index = SpatialHash::new(50);
+        index.update(&hitboxes);
+
+        // Move some, remove some, add some.
+        for (i, &id) in ids[..500].iter().enumerate() {
+            match i % 3 {
+                0 => hitboxes[id] = hitboxes[id] + Point::new(i as i32 % 70, -3),
+                1 => {
+                    hitboxes.remove(id);
+                }
+                _ => {}
+            }
+        }
+        for &id in &ids[500..] {
+            hitboxes.insert(id, all[id]);
+        }
+        index.update(&hitboxes);
+
+        let mut fresh = SpatialHash::new(50);
+        fresh.update(&hitboxes);
+        assert_eq!(index.len(), hitboxes.len());
+        assert_eq!(index.pairs(), fresh.pairs());
+        assert_eq!(index.pairs(), brute_force_pairs(&hitboxes));
+
+        index.clear();
+        assert!(index.is_empty());
+        assert!(index.pairs().is_empty());
+    }
+}
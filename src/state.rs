@@ -1,27 +1,250 @@
+use serde::{Deserialize, Serialize};
+use slotmap::{SecondaryMap, SlotMap};
+
+use crate::direction::Direction;
+use crate::event::Events;
+use crate::fixed::Fixed;
+use crate::point::Point;
 use crate::rect::Rect;
+use crate::rng::Rng;
+use crate::spatial::SpatialHash;
+use crate::tilemap::TileMap;
+use crate::wave::Waves;
+use crate::weapon::Weapon;
 
 pub type EntityId = slotmap::DefaultKey;
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+pub type Components<T> = SecondaryMap<EntityId, T>;
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum EntityKind {
     Player,
     Monster,
     Power,
 }
 
-#[derive(Copy, Clone)]
-pub struct Entity {
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Attack {
+    pub frame: Option<usize>, // ticks into the current swing
+    pub cooldown: u32,        // ticks until the next swing can start
     pub hitbox: Rect,
-    pub kind: EntityKind,
-    pub facing_direction: i32, // 0/1/2/3 for up/left/down/right
-    pub attack_frame: Option<usize>,
-    pub attack_box: Rect,
+    // What the current swing already hit, so each one is only hit once.
+    pub hit: Vec<EntityId>,
+}
+
+// Weapons held, as indices into State::weapons.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Inventory {
+    pub weapons: Vec<usize>,
+    pub current: usize,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Ai {
     pub agro: i32,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Stats {
     pub score: i32,
     pub power: i32,
 }
 
-#[derive(Default)]
+// Entities are just ids. Their data lives in separate component maps,
+// so each entity only carries the components it actually uses.
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct State {
-    pub entities: slotmap::SlotMap<EntityId, Entity>,
+    pub entities: SlotMap<EntityId, ()>,
+    pub hitbox: Components<Rect>,
+    // Where moving entities really are, to a fraction of a pixel, and how
+    // far they move each frame. Their hitbox is the pixel position.
+    pub position: Components<Point<Fixed>>,
+    pub velocity: Components<Point<Fixed>>,
+    pub kind: Components<EntityKind>,
+    pub facing: Components<Direction>,
+    pub attack: Components<Attack>,
+    pub inventory: Components<Inventory>,
+    pub health: Components<i32>,
+    pub ai: Components<Ai>,
+    pub stats: Components<Stats>,
+    // Players' stats as of when they died, since despawning them takes
+    // the rest of their components away. Older saves don't have these.
+    #[serde(default)]
+    pub final_stats: Components<Stats>,
+
+    pub tiles: TileMap,
+    pub weapons: Vec<Weapon>,
+
+    // Drives all randomness in the simulation (spawn position, facing
+    // and timing), so a seed plus the inputs determine the whole game.
+    pub rng: Rng,
+    pub waves: Waves,
+    pub kills: u32,
+    pub next_power_frame: u64,
+
+    // What happened during the current frame. Not part of the saved
+    // game, since it's cleared every frame anyway.
+    #[serde(skip)]
+    pub events: Events,
+
+    // Index over the hitboxes for finding what overlaps what. Brought up
+    // to date by whoever uses it, so it isn't saved either.
+    #[serde(skip)]
+    pub spatial: SpatialHash,
+}
+
+impl State {
+    pub fn new(seed: u64) -> State {
+        State {
+            rng: Rng::new(seed),
+            next_power_frame: 150,
+            ..State::default()
+        }
+    }
+
+    pub fn spawn(&mut self, kind: EntityKind, hitbox: Rect) -> EntityId {
+        let id = self.entities.insert(());
+        self.kind.insert(id, kind);
+        self.hitbox.insert(id, hitbox);
+        id
+    }
+
+    fn spawn_moving(&mut self, kind: EntityKind, hitbox: Rect) -> EntityId {
+        let id = self.spawn(kind, hitbox);
+        self.position.insert(id, hitbox.lo.into());
+        self.velocity.insert(id, Point::default());
+        id
+    }
+
+    pub fn spawn_player(&mut self, hitbox: Rect) -> EntityId {
+        let id = self.spawn_moving(EntityKind::Player, hitbox);
+        self.facing.insert(id, Direction::Up);
+        self.attack.insert(id, Attack::default());
+        self.inventory.insert(
+            id,
+            Inventory {
+                weapons: (0..self.weapons.len()).collect(),
+                current: 0,
+            },
+        );
+        self.stats.insert(id, Stats::default());
+        id
+    }
+
+    pub fn spawn_monster(&mut self, hitbox: Rect, facing: Direction) -> EntityId {
+        let id = self.spawn_moving(EntityKind::Monster, hitbox);
+        self.facing.insert(id, facing);
+        self.health.insert(id, 1);
+        self.ai.insert(id, Ai::default());
+        id
+    }
+
+    pub fn spawn_power(&mut self, hitbox: Rect) -> EntityId {
+        self.spawn(EntityKind::Power, hitbox)
+    }
+
+    pub fn despawn(&mut self, id: EntityId) {
+        self.entities.remove(id);
+        self.hitbox.remove(id);
+        self.position.remove(id);
+        self.velocity.remove(id);
+        self.kind.remove(id);
+        self.facing.remove(id);
+        self.attack.remove(id);
+        self.inventory.remove(id);
+        self.health.remove(id);
+        self.ai.remove(id);
+        self.stats.remove(id);
+    }
+
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.entities.contains_key(id)
+    }
+
+    // The weapon an entity is currently holding.
+    pub fn weapon(&self, id: EntityId) -> Option<&Weapon> {
+        let inventory = self.inventory.get(id)?;
+        self.weapons.get(*inventory.weapons.get(inventory.current)?)
+    }
+}
+
+// Queries: iterate over the entities having every one of the given
+// components. The first component may be borrowed mutably.
+
+pub fn join<'a, A, B>(
+    a: &'a Components<A>,
+    b: &'a Components<B>,
+) -> impl Iterator<Item = (EntityId, &'a A, &'a B)> {
+    a.iter()
+        .filter_map(move |(id, a)| Some((id, a, b.get(id)?)))
+}
+
+pub fn join3<'a, A, B, C>(
+    a: &'a Components<A>,
+    b: &'a Components<B>,
+    c: &'a Components<C>,
+) -> impl Iterator<Item = (EntityId, &'a A, &'a B, &'a C)> {
+    a.iter()
+        .filter_map(move |(id, a)| Some((id, a, b.get(id)?, c.get(id)?)))
+}
+
+pub fn join_mut<'a, A, B>(
+    a: &'a mut Components<A>,
+    b: &'a Components<B>,
+) -> impl Iterator<Item = (EntityId, &'a mut A, &'a B)> {
+    a.iter_mut()
+        .filter_map(move |(id, a)| Some((id, a, b.get(id)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_spawn_despawn() {
+        let mut state = State::default();
+        let player = state.spawn_player(Rect::new_with_size(0, 0, 4, 4));
+        let monster = state.spawn_monster(Rect::new_with_size(8, 0, 4, 4), Direction::Left);
+        let power = state.spawn_power(Rect::new_with_size(0, 8, 4, 4));
+
+        assert_eq!(state.kind[player], EntityKind::Player);
+        assert!(state.stats.contains_key(player));
+        assert!(!state.ai.contains_key(player));
+        assert_eq!(state.facing[monster], Direction::Left);
+        assert!(state.ai.contains_key(monster));
+        assert!(!state.stats.contains_key(monster));
+        assert!(!state.facing.contains_key(power));
+        assert_eq!(state.position[monster], Point::new(8, 0).into());
+        assert!(!state.velocity.contains_key(power));
+
+        state.despawn(monster);
+        assert!(!state.contains(monster));
+        assert!(state.hitbox.get(monster).is_none());
+        assert!(state.facing.get(monster).is_none());
+        assert!(state.position.get(monster).is_none());
+        assert_eq!(state.hitbox.len(), 2);
+    }
+
+    #[test]
+    fn state_join() {
+        let mut state = State::default();
+        let player = state.spawn_player(Rect::new_with_size(0, 0, 4, 4));
+        let monster = state.spawn_monster(Rect::new_with_size(8, 0, 4, 4), Direction::UpLeft);
+        state.spawn_power(Rect::new_with_size(0, 8, 4, 4));
+
+        let facing: Vec<_> = join(&state.facing, &state.hitbox)
+            .map(|(id, _, _)| id)
+            .collect();
+        assert_eq!(facing, vec![player, monster]);
+
+        let monsters: Vec<_> = join3(&state.ai, &state.facing, &state.hitbox)
+            .map(|(id, _, &facing, _)| (id, facing))
+            .collect();
+        assert_eq!(monsters, vec![(monster, Direction::UpLeft)]);
+
+        for (_, ai, _) in join_mut(&mut state.ai, &state.hitbox) {
+            ai.agro += 10;
+        }
+        assert_eq!(state.ai[monster].agro, 10);
+    }
 }
@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::point::Point;
+use crate::rect::Rect;
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Tile {
+    Floor,
+    Wall,
+}
+
+impl Tile {
+    pub fn is_solid(self) -> bool {
+        self == Tile::Wall
+    }
+}
+
+// A grid of square tiles covering the arena, from the top-left corner.
+// Anything outside the grid is floor; the arena edge is handled by
+// clamping instead.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TileMap {
+    pub tile_size: i32,
+    pub width: i32,
+    pub height: i32,
+    tiles: Vec<Tile>,
+}
+
+impl TileMap {
+    pub fn new(width: i32, height: i32, tile_size: i32) -> TileMap {
+        TileMap {
+            tile_size,
+            width,
+            height,
+            tiles: vec![Tile::Floor; (width * height) as usize],
+        }
+    }
+
+    // Builds a map from rows of text, '#' for walls and '.' for floor.
+    // Missing rows and columns are floor.
+    pub fn parse(
+        rows: &[String],
+        width: i32,
+        height: i32,
+        tile_size: i32,
+    ) -> Result<TileMap, Error> {
+        let mut map = TileMap::new(width, height, tile_size);
+        if rows.len() > height as usize {
+            return Err(Error::LevelError(format!(
+                "tile map has {} rows, but the arena only fits {}",
+                rows.len(),
+                height
+            )));
+        }
+        for (y, row) in rows.iter().enumerate() {
+            if row.chars().count() > width as usize {
+                return Err(Error::LevelError(format!(
+                    "tile row {} has {} tiles, but the arena only fits {}",
+                    y,
+                    row.chars().count(),
+                    width
+                )));
+            }
+            for (x, c) in row.chars().enumerate() {
+                let tile = match c {
+                    '#' => Tile::Wall,
+                    '.' => Tile::Floor,
+                    c => {
+                        return Err(Error::LevelError(format!(
+                            "unknown tile '{}' at ({}, {})",
+                            c, x, y
+                        )))
+                    }
+                };
+                map.set(x as i32, y as i32, tile);
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> Tile {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return Tile::Floor;
+        }
+        self.tiles[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, tile: Tile) {
+        if x >= 0 && y >= 0 && x < self.width && y < self.height {
+            self.tiles[(y * self.width + x) as usize] = tile;
+        }
+    }
+
+    pub fn tile_rect(&self, x: i32, y: i32) -> Rect {
+        let lo = Point::new(x, y) * self.tile_size;
+        Rect::new(lo, lo + self.tile_size)
+    }
+
+    // The solid tiles, in row order.
+    pub fn walls(&self) -> impl Iterator<Item = Rect> + '_ {
+        (0..self.height)
+            .flat_map(move |y| (0..self.width).map(move |x| (x, y)))
+            .filter(move |&(x, y)| self.get(x, y).is_solid())
+            .map(move |(x, y)| self.tile_rect(x, y))
+    }
+
+    pub fn is_blocked(&self, r: Rect) -> bool {
+        if self.tile_size <= 0 || r.is_empty() {
+            return false;
+        }
+        let lo = Point::new(
+            r.lo.x.div_euclid(self.tile_size),
+            r.lo.y.div_euclid(self.tile_size),
+        );
+        let hi = Point::new(
+            (r.hi.x - 1).div_euclid(self.tile_size),
+            (r.hi.y - 1).div_euclid(self.tile_size),
+        );
+        (lo.y..=hi.y).any(|y| (lo.x..=hi.x).any(|x| self.get(x, y).is_solid()))
+    }
+
+    // Moves a rect by delta, one axis at a time so that it can slide
+    // along walls, stopping just short of any solid tile.
+    pub fn move_rect(&self, r: Rect, delta: Point) -> Rect {
+        let r = self.move_axis(r, Point::new(delta.x, 0));
+        self.move_axis(r, Point::new(0, delta.y))
+    }
+
+    fn move_axis(&self, mut r: Rect, delta: Point) -> Rect {
+        let step = Point::new(delta.x.signum(), delta.y.signum());
+        for _ in 0..(delta.x.abs() + delta.y.abs()) {
+            let next = r + step;
+            if self.is_blocked(next) {
+                break;
+            }
+            r = next;
+        }
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map() -> TileMap {
+        let rows: Vec<String> = vec!["....".into(), ".#..".into(), "...#".into()];
+        TileMap::parse(&rows, 4, 4, 10).unwrap()
+    }
+
+    #[test]
+    fn tilemap_parse() {
+        let map = map();
+        assert_eq!(map.get(1, 1), Tile::Wall);
+        assert_eq!(map.get(3, 2), Tile::Wall);
+        assert_eq!(map.get(0, 3), Tile::Floor);
+        assert_eq!(map.get(-1, 0), Tile::Floor);
+        assert_eq!(
+            map.walls().collect::<Vec<_>>(),
+            vec![
+                Rect::new(Point::new(10, 10), Point::new(20, 20)),
+                Rect::new(Point::new(30, 20), Point::new(40, 30)),
+            ]
+        );
+
+        assert!(TileMap::parse(&["....x".into()], 4, 4, 10).is_err());
+        assert!(TileMap::parse(&["?".into()], 4, 4, 10).is_err());
+        assert!(TileMap::parse(&vec![".".to_string(); 5], 4, 4, 10).is_err());
+    }
+
+    #[test]
+    fn tilemap_is_blocked() {
+        let map = map();
+        assert!(!map.is_blocked(Rect::new(Point::new(0, 0), Point::new(10, 10))));
+        assert!(map.is_blocked(Rect::new(Point::new(0, 0), Point::new(11, 11))));
+        assert!(map.is_blocked(Rect::new(Point::new(19, 19), Point::new(20, 20))));
+        assert!(!map.is_blocked(Rect::new(Point::new(20, 10), Point::new(30, 20))));
+    }
+
+    #[test]
+    fn tilemap_move_rect() {
+        let map = map();
+        let r = Rect::new(Point::new(0, 10), Point::new(8, 18));
+
+        // Stops flush against the wall.
+        assert_eq!(map.move_rect(r, Point::new(5, 0)), r + Point::new(2, 0));
+
+        // Slides along it when moving diagonally.
+        assert_eq!(map.move_rect(r, Point::new(5, 3)), r + Point::new(2, 3));
+
+        // Unobstructed.
+        assert_eq!(map.move_rect(r, Point::new(-3, -3)), r + Point::new(-3, -3));
+    }
+}
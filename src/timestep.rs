@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+// Never try to catch up on more than this much real time at once
+// (e.g. after the window was dragged or the process was suspended),
+// otherwise a slow frame causes more updates, which cause a slower
+// frame, and so on.
+const MAX_ELAPSED: Duration = Duration::from_millis(250);
+
+// Converts elapsed real time into a whole number of fixed-length
+// simulation ticks. Whatever time is left over is carried into the
+// next frame, and exposed as an interpolation factor for rendering.
+#[derive(Debug)]
+pub struct Timestep {
+    tick: Duration,
+    accumulator: Duration,
+}
+
+impl Timestep {
+    pub fn new(tick_rate: u32) -> Timestep {
+        assert!(tick_rate > 0);
+        Timestep {
+            tick: Duration::from_secs(1) / tick_rate,
+            accumulator: Duration::default(),
+        }
+    }
+
+    pub fn tick(&self) -> Duration {
+        self.tick
+    }
+
+    // Returns the number of ticks to simulate for this frame.
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.accumulator += elapsed.min(MAX_ELAPSED);
+        let mut ticks = 0;
+        while self.accumulator >= self.tick {
+            self.accumulator -= self.tick;
+            ticks += 1;
+        }
+        ticks
+    }
+
+    // How far we are between the last tick and the next one, in 0..1.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.tick.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestep_advance() {
+        let mut t = Timestep::new(50);
+        assert_eq!(t.tick(), Duration::from_millis(20));
+
+        assert_eq!(t.advance(Duration::from_millis(10)), 0);
+        assert!((t.alpha() - 0.5).abs() < 1e-6);
+        assert_eq!(t.advance(Duration::from_millis(10)), 1);
+        assert!(t.alpha().abs() < 1e-6);
+        assert_eq!(t.advance(Duration::from_millis(65)), 3);
+        assert!((t.alpha() - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn timestep_max_elapsed() {
+        let mut t = Timestep::new(100);
+        assert_eq!(t.advance(Duration::from_secs(10)), 25);
+    }
+}
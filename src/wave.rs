@@ -0,0 +1,297 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use crate::error::Error;
+use crate::point::Point;
+use crate::rng::Rng;
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Trigger {
+    // Once, at the given frame.
+    Frame(u64),
+    // Once, when the player has killed this many monsters in total.
+    Kills(u32),
+    // Repeatedly from the given frame, every period frames give or take
+    // up to jitter frames.
+    Every {
+        start: u64,
+        period: u64,
+        #[serde(default)]
+        jitter: u64,
+    },
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum Edge {
+    Top,
+    Left,
+    Bottom,
+    Right,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum SpawnPoint {
+    // Random position along a random edge of the arena.
+    #[default]
+    AnyEdge,
+    // Random position along the given edge.
+    Edge(Edge),
+    // Top-left corner of the monster.
+    At(Point),
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum MonsterType {
+    // Wanders in a straight line, then starts chasing the player.
+    #[default]
+    Wanderer,
+    // Chases the player from the moment it spawns, and takes two hits.
+    Chaser,
+}
+
+impl MonsterType {
+    pub fn initial_agro(self) -> i32 {
+        match self {
+            MonsterType::Wanderer => 0,
+            MonsterType::Chaser => 320,
+        }
+    }
+
+    pub fn health(self) -> i32 {
+        match self {
+            MonsterType::Wanderer => 1,
+            MonsterType::Chaser => 2,
+        }
+    }
+}
+
+fn one() -> u32 {
+    1
+}
+
+// A group of monsters spawned when the trigger fires, one every delay
+// frames. See waves/*.ron for examples.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Wave {
+    pub trigger: Trigger,
+    #[serde(default = "one")]
+    pub count: u32,
+    #[serde(default)]
+    pub spawn: SpawnPoint,
+    #[serde(default)]
+    pub monster: MonsterType,
+    #[serde(default)]
+    pub delay: u64,
+}
+
+pub fn load_waves<P: AsRef<Path>>(path: P) -> Result<Vec<Wave>, Error> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)?;
+    parse_waves(&text).map_err(|e| match e {
+        Error::DeserializeError(e) => Error::LevelError(format!("{}: {}", path.display(), e)),
+        e => e,
+    })
+}
+
+pub fn parse_waves(text: &str) -> Result<Vec<Wave>, Error> {
+    let waves: Vec<Wave> = ron::from_str(text)?;
+    validate_waves(&waves)?;
+    Ok(waves)
+}
+
+// Checks what can be checked without knowing the arena. Levels check
+// the spawn points themselves.
+pub fn validate_waves(waves: &[Wave]) -> Result<(), Error> {
+    for (i, wave) in waves.iter().enumerate() {
+        if let Trigger::Every { period, jitter, .. } = wave.trigger {
+            if period == 0 || jitter >= period {
+                return Err(Error::LevelError(format!(
+                    "wave {} must have a period greater than its jitter",
+                    i
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn default_waves() -> Vec<Wave> {
+    parse_waves(include_str!("../waves/default.ron")).expect("default waves are valid")
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Spawn {
+    pub frame: u64,
+    pub point: SpawnPoint,
+    pub monster: MonsterType,
+}
+
+// Progress through a wave schedule.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Waves {
+    pub schedule: Vec<Wave>,
+    // Next frame each wave can trigger on, or None once it's done.
+    next_frame: Vec<Option<u64>>,
+    pending: Vec<Spawn>,
+}
+
+impl Waves {
+    pub fn new(schedule: Vec<Wave>) -> Waves {
+        let next_frame = schedule
+            .iter()
+            .map(|wave| match wave.trigger {
+                Trigger::Frame(frame) => Some(frame),
+                Trigger::Kills(_) => Some(0),
+                Trigger::Every { start, .. } => Some(start),
+            })
+            .collect();
+        Waves {
+            schedule,
+            next_frame,
+            pending: Vec::new(),
+        }
+    }
+
+    // Where this is up to in the schedule, without the schedule itself,
+    // which never changes. For going back to with restore.
+    pub fn progress(&self) -> Waves {
+        Waves {
+            schedule: Vec::new(),
+            next_frame: self.next_frame.clone(),
+            pending: self.pending.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, progress: &Waves) {
+        self.next_frame.clone_from(&progress.next_frame);
+        self.pending.clone_from(&progress.pending);
+    }
+
+    pub fn hash_progress<H: Hasher>(&self, hasher: &mut H) {
+        self.next_frame.hash(hasher);
+        self.pending.hash(hasher);
+    }
+
+    // Returns the monsters to spawn this frame, in schedule order.
+    pub fn update(&mut self, frame: u64, kills: u32, rng: &mut Rng) -> Vec<Spawn> {
+        for (wave, next) in self.schedule.iter().zip(self.next_frame.iter_mut()) {
+            let due = match (wave.trigger, *next) {
+                (_, None) => false,
+                (Trigger::Kills(target), Some(_)) => kills >= target,
+                (_, Some(next)) => frame >= next,
+            };
+            if !due {
+                continue;
+            }
+
+            *next = match wave.trigger {
+                Trigger::Every { period, jitter, .. } => {
+                    let jitter = jitter as i32;
+                    Some(frame + (period as i32 + rng.range(-jitter, jitter + 1)) as u64)
+                }
+                _ => None,
+            };
+            for i in 0..wave.count as u64 {
+                self.pending.push(Spawn {
+                    frame: frame + i * wave.delay,
+                    point: wave.spawn,
+                    monster: wave.monster,
+                });
+            }
+        }
+
+        let (due, pending) = self.pending.iter().partition(|spawn| spawn.frame <= frame);
+        self.pending = pending;
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wave(trigger: Trigger, count: u32, delay: u64) -> Wave {
+        Wave {
+            trigger,
+            count,
+            spawn: SpawnPoint::AnyEdge,
+            monster: MonsterType::Wanderer,
+            delay,
+        }
+    }
+
+    fn frames_with_spawns(waves: &mut Waves, kills: &dyn Fn(u64) -> u32) -> Vec<u64> {
+        let mut rng = Rng::new(0);
+        let mut frames = Vec::new();
+        for frame in 0..100 {
+            for _ in waves.update(frame, kills(frame), &mut rng) {
+                frames.push(frame);
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn waves_frame_trigger() {
+        let mut waves = Waves::new(vec![wave(Trigger::Frame(10), 3, 5)]);
+        assert_eq!(frames_with_spawns(&mut waves, &|_| 0), vec![10, 15, 20]);
+
+        let mut waves = Waves::new(vec![wave(Trigger::Frame(10), 2, 0)]);
+        assert_eq!(frames_with_spawns(&mut waves, &|_| 0), vec![10, 10]);
+    }
+
+    #[test]
+    fn waves_kills_trigger() {
+        let mut waves = Waves::new(vec![wave(Trigger::Kills(2), 2, 1)]);
+        let kills = |frame| (frame / 30) as u32;
+        assert_eq!(frames_with_spawns(&mut waves, &kills), vec![60, 61]);
+    }
+
+    #[test]
+    fn waves_every_trigger() {
+        let trigger = Trigger::Every {
+            start: 5,
+            period: 20,
+            jitter: 0,
+        };
+        let mut waves = Waves::new(vec![wave(trigger, 1, 0)]);
+        assert_eq!(
+            frames_with_spawns(&mut waves, &|_| 0),
+            vec![5, 25, 45, 65, 85]
+        );
+
+        let trigger = Trigger::Every {
+            start: 0,
+            period: 20,
+            jitter: 5,
+        };
+        let mut waves = Waves::new(vec![wave(trigger, 1, 0)]);
+        let frames = frames_with_spawns(&mut waves, &|_| 0);
+        assert_eq!(frames[0], 0);
+        for pair in frames.windows(2) {
+            assert!((15..=25).contains(&(pair[1] - pair[0])));
+        }
+    }
+
+    #[test]
+    fn waves_parse() {
+        assert!(!default_waves().is_empty());
+        assert!(parse_waves(include_str!("../waves/escalating.ron")).is_ok());
+        let waves = parse_waves(
+            "[(trigger: Kills(5), count: 3, spawn: Edge(Top), monster: Chaser, delay: 10)]",
+        )
+        .unwrap();
+        assert_eq!(waves[0].spawn, SpawnPoint::Edge(Edge::Top));
+        assert_eq!(waves[0].monster, MonsterType::Chaser);
+
+        let waves = parse_waves("[(trigger: Frame(0), spawn: At((x: 1, y: 2)))]").unwrap();
+        assert_eq!(waves[0].count, 1);
+        assert_eq!(waves[0].spawn, SpawnPoint::At(Point::new(1, 2)));
+
+        assert!(parse_waves("[(trigger: Every(start: 0, period: 0))]").is_err());
+        assert!(parse_waves("[(trigger: Sometimes)]").is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::min;
+use std::fs;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::point::Point;
+use crate::rect::Rect;
+
+// A value that grows with the wielder's power.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Scaling {
+    pub base: i32,
+    pub per_power: i32,
+}
+
+impl Scaling {
+    pub fn at(self, power: i32) -> i32 {
+        self.base + self.per_power * power
+    }
+}
+
+// See weapons/default.ron for the format.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Weapon {
+    pub name: String,
+    // Hitbox of each frame of the swing as (x, y, width, height),
+    // relative to the middle of the wielder's front edge, facing up.
+    pub frames: Vec<(i32, i32, i32, i32)>,
+    // How many ticks the swing lasts. The frames are spread evenly over
+    // it.
+    pub duration: usize,
+    // How many ticks after a swing before the next one can start.
+    #[serde(default)]
+    pub cooldown: u32,
+    // How far each frame reaches out from the wielder, cutting off the
+    // far end of it. Unlimited if left out.
+    #[serde(default)]
+    pub reach: Option<Scaling>,
+    // Extra width added to each frame, split between both sides.
+    #[serde(default)]
+    pub width: Scaling,
+    pub damage: i32,
+}
+
+impl Weapon {
+    // The hitbox on the given tick of a swing, facing up, for a wielder
+    // with the given power.
+    pub fn hitbox(&self, tick: usize, power: i32) -> Rect {
+        let index = min(
+            tick * self.frames.len() / self.duration,
+            self.frames.len() - 1,
+        );
+        let frame: Rect = self.frames[index].into();
+        let lo_y = match self.reach {
+            Some(reach) => frame.hi.y - min(frame.height() as i32, reach.at(power)),
+            None => frame.lo.y,
+        };
+        let extra = self.width.at(power);
+        Rect::new(
+            Point::new(frame.lo.x - extra / 2, lo_y),
+            Point::new(frame.hi.x + extra - extra / 2, frame.hi.y),
+        )
+    }
+}
+
+pub fn load_weapons<P: AsRef<Path>>(path: P) -> Result<Vec<Weapon>, Error> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)?;
+    parse_weapons(&text).map_err(|e| match e {
+        Error::DeserializeError(e) => Error::LevelError(format!("{}: {}", path.display(), e)),
+        e => e,
+    })
+}
+
+pub fn parse_weapons(text: &str) -> Result<Vec<Weapon>, Error> {
+    let weapons: Vec<Weapon> = ron::from_str(text)?;
+    validate_weapons(&weapons)?;
+    Ok(weapons)
+}
+
+pub fn validate_weapons(weapons: &[Weapon]) -> Result<(), Error> {
+    for weapon in weapons {
+        if weapon.frames.is_empty() || weapon.duration == 0 {
+            return Err(Error::LevelError(format!(
+                "weapon {} needs at least one frame and a duration",
+                weapon.name
+            )));
+        }
+        if weapon.frames.iter().any(|&(_, _, w, h)| w <= 0 || h <= 0) {
+            return Err(Error::LevelError(format!(
+                "weapon {} has an empty frame",
+                weapon.name
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub fn default_weapons() -> Vec<Weapon> {
+    parse_weapons(include_str!("../weapons/default.ron")).expect("default weapons are valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weapon_hitbox() {
+        let weapons = default_weapons();
+        let sword = &weapons[0];
+        assert_eq!(sword.name, "sword");
+
+        // Reach is cut off at 5 pixels per point of power, plus 5.
+        let frame: Rect = sword.frames[2].into();
+        assert_eq!(sword.hitbox(2, 0).hi, frame.hi);
+        assert_eq!(sword.hitbox(2, 0).height(), 5);
+        assert_eq!(sword.hitbox(2, 1).height(), 10);
+        assert_eq!(sword.hitbox(2, 8), frame);
+    }
+
+    #[test]
+    fn weapon_frames_and_width() {
+        let weapon = Weapon {
+            name: "test".to_string(),
+            frames: vec![(0, -10, 4, 10), (4, -10, 4, 10)],
+            duration: 6,
+            cooldown: 0,
+            reach: None,
+            width: Scaling {
+                base: 1,
+                per_power: 2,
+            },
+            damage: 1,
+        };
+        assert_eq!(weapon.hitbox(2, 0), weapon.hitbox(0, 0));
+        assert_eq!(weapon.hitbox(3, 0).lo.x, 4);
+        assert_eq!(weapon.hitbox(5, 0).lo.x, 4);
+
+        let wide = weapon.hitbox(0, 2);
+        assert_eq!((wide.lo.x, wide.hi.x), (-2, 3 + 3));
+    }
+
+    #[test]
+    fn weapon_parse_errors() {
+        assert!(parse_weapons("[(name: \"x\", frames: [], duration: 1, damage: 1)]").is_err());
+        assert!(
+            parse_weapons("[(name: \"x\", frames: [(0, 0, 1, 1)], duration: 0, damage: 1)]")
+                .is_err()
+        );
+        assert!(
+            parse_weapons("[(name: \"x\", frames: [(0, 0, 0, 1)], duration: 1, damage: 1)]")
+                .is_err()
+        );
+        assert!(
+            parse_weapons("[(name: \"x\", frames: [(0, 0, 1, 1)], duration: 1, damage: 1)]")
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,183 @@
+// Renders fixed scenes and compares them against the images checked in
+// under tests/golden. After an intentional rendering change, rerun with
+// UPDATE_GOLDEN=1 and check in the updated images.
+
+use std::path::PathBuf;
+
+use game::direction::Direction;
+use game::framebuffer::Framebuffer;
+use game::level::Level;
+use game::point::Point;
+use game::rect::Rect;
+use game::render::render_scene;
+use game::scene::Scene;
+use game::sim::World;
+use game::tilemap::TileMap;
+
+// Allowed per-channel difference, and number of pixels allowed to
+// exceed it.
+const TOLERANCE: u8 = 2;
+const MAX_DIFFERENT_PIXELS: usize = 0;
+
+fn check(name: &str, world: &World) {
+    check_scene(name, Scene::Playing, world);
+}
+
+fn check_scene(name: &str, scene: Scene, world: &World) {
+    let mut actual = Framebuffer::new(world.arena.width(), world.arena.height());
+    render_scene(&mut actual, scene, world, 1.0).unwrap();
+
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let path = dir.join(format!("{}.ppm", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        actual.write_ppm(&path).unwrap();
+        return;
+    }
+
+    let expected = Framebuffer::read_ppm(&path)
+        .unwrap_or_else(|e| panic!("can't read {}: {:?}", path.display(), e));
+    let diff = actual.diff(&expected, TOLERANCE);
+    if diff.is_none_or(|count| count > MAX_DIFFERENT_PIXELS) {
+        let out = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.actual.ppm", name));
+        actual.write_ppm(&out).unwrap();
+        panic!(
+            "{} differs from golden image ({:?} pixels), actual image written to {}",
+            name,
+            diff,
+            out.display()
+        );
+    }
+}
+
+fn world() -> World {
+    let level = Level {
+        width: 160,
+        height: 120,
+        player: Point::new(0, 0),
+        co_op_starts: Vec::new(),
+        monsters: Vec::new(),
+        powers: Vec::new(),
+        tile_size: 16,
+        tiles: Vec::new(),
+        waves: Vec::new(),
+        weapons: Vec::new(),
+    };
+    let mut world = World::new(&level, 0);
+    let ids: Vec<_> = world.state.entities.keys().collect();
+    for id in ids {
+        world.state.despawn(id);
+    }
+    world
+}
+
+#[test]
+fn golden_entities() {
+    let mut world = world();
+    let state = &mut world.state;
+
+    // Players facing each direction, with varying power and attacks.
+    let player = state.spawn_player(Rect::new_with_size(16, 16, 32, 32));
+    state.stats[player].score = 10;
+    state.attack[player].hitbox = Rect::new_with_size(28, 2, 8, 14);
+    world.players = vec![player];
+
+    let player = state.spawn_player(Rect::new_with_size(64, 16, 32, 32));
+    state.facing[player] = Direction::Left;
+    state.stats[player].power = 4;
+    state.attack[player].hitbox = Rect::new_with_size(52, 24, 12, 8);
+
+    let player = state.spawn_player(Rect::new_with_size(112, 16, 32, 32));
+    state.facing[player] = Direction::Down;
+    state.stats[player].power = 8;
+
+    let player = state.spawn_player(Rect::new_with_size(16, 72, 32, 32));
+    state.facing[player] = Direction::Right;
+
+    // Monsters at increasing agro, and a power-up.
+    state.spawn_monster(Rect::new_with_size(64, 72, 32, 32), Direction::Up);
+    let monster = state.spawn_monster(Rect::new_with_size(104, 64, 24, 24), Direction::Up);
+    state.ai[monster].agro = 700;
+    state.spawn_power(Rect::new_with_size(136, 96, 16, 16));
+
+    check("entities", &world);
+}
+
+#[test]
+fn golden_game_over() {
+    let mut world = world();
+    world
+        .state
+        .spawn_monster(Rect::new_with_size(64, 44, 32, 32), Direction::Up);
+    check("game_over", &world);
+}
+
+#[test]
+fn golden_scenes() {
+    let mut world = world();
+    let state = &mut world.state;
+    let player = state.spawn_player(Rect::new_with_size(64, 44, 32, 32));
+    state.stats[player].score = 120;
+    world.players = vec![player];
+    state.spawn_monster(Rect::new_with_size(16, 16, 24, 24), Direction::Up);
+    check_scene("title", Scene::Title, &world);
+    check_scene("paused", Scene::Paused, &world);
+
+    // The final score stays on screen once the player is gone, with text
+    // too wide at its usual size drawn smaller.
+    world
+        .state
+        .final_stats
+        .insert(player, world.state.stats[player]);
+    world.state.despawn(player);
+    check_scene("game_over_scene", Scene::GameOver, &world);
+}
+
+#[test]
+fn golden_walls() {
+    let mut world = world();
+    let rows: Vec<String> = [
+        "##########",
+        "#........#",
+        "#..##....#",
+        "#..#.....#",
+        "#......#.#",
+        "#......#.#",
+        "#........#",
+        "##########",
+    ]
+    .iter()
+    .map(|row| row.to_string())
+    .collect();
+    world.state.tiles = TileMap::parse(&rows, 10, 8, 16).unwrap();
+
+    let state = &mut world.state;
+    world.players = vec![state.spawn_player(Rect::new_with_size(80, 32, 32, 32))];
+    state.spawn_monster(Rect::new_with_size(24, 72, 24, 24), Direction::Up);
+    check("walls", &world);
+}
+
+#[test]
+fn golden_diagonals() {
+    let mut world = world();
+    let state = &mut world.state;
+
+    // Players facing each diagonal, one of them mid-swing.
+    let diagonals = [
+        Direction::UpLeft,
+        Direction::DownLeft,
+        Direction::DownRight,
+        Direction::UpRight,
+    ];
+    for (i, &facing) in diagonals.iter().enumerate() {
+        let (x, y) = (16 + 80 * (i as i32 % 2), 16 + 56 * (i as i32 / 2));
+        let player = state.spawn_player(Rect::new_with_size(x, y, 32, 32));
+        state.facing[player] = facing;
+        world.players = vec![player];
+    }
+    let hitbox = state.hitbox[world.players[0]];
+    state.attack[world.players[0]].hitbox = Rect::new_with_size(-2, -20, 4, 20)
+        .rotate(Point::new(0, 0), Direction::UpRight.angle())
+        + hitbox.index(1, -1);
+
+    check("diagonals", &world);
+}